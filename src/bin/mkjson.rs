@@ -1,23 +1,438 @@
+use clap::CommandFactory;
 use clap::Parser;
-use mkjson::composer::compose;
+use clap::Subcommand;
+use clap::ValueEnum;
+use clap::error::ErrorKind;
+use mkjson::binary::to_cbor;
+use mkjson::binary::to_msgpack;
+use mkjson::composer::Options;
+use mkjson::composer::apply;
+use mkjson::composer::compose_with;
+use mkjson::diff::diff;
+use mkjson::diff::to_directives;
+use mkjson::diff::to_report;
+use mkjson::input::Input;
+use mkjson::input::expand_args;
+use mkjson::input::parse_variable_definition;
+use mkjson::input::read_vars_file;
+use mkjson::input::split_documents;
+use mkjson::input::split_nul_separated;
+use mkjson::node::Node;
+use mkjson::output::Frame;
+use mkjson::output::Style;
+use mkjson::output::explode;
+use mkjson::output::to_canonical_json;
+use mkjson::output::to_json;
+use mkjson::output::write_framed;
+use mkjson::records::RecordError;
+use mkjson::records::RecordFormat;
+use mkjson::records::Records;
+use mkjson::records::check_templates;
+use mkjson::records::compose_record;
+use mkjson::toml::to_toml;
+use mkjson::yaml::to_yaml;
+use std::io::BufWriter;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
 use std::process::ExitCode;
 
 /// Command-Line JSON Composer
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
-    /// Directives (e.g., a.b:true c.0.d=foobar)
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directives (e.g., a.b:true c.0.d=foobar), or @FILE to read directives from a file, or ,,
+    /// to start the directives of another document
     #[arg(id = "DIRECTIVE")]
     directives: Vec<Vec<u8>>,
+
+    /// Also read NUL-separated directives from stdin
+    #[arg(short = '0', long)]
+    stdin: bool,
+
+    /// Define a variable for interpolating string directives (e.g., host=example.com)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    vars: Vec<(String, String)>,
+
+    /// Read variable definitions from a file, one NAME=VALUE per line
+    #[arg(long, value_name = "FILE")]
+    vars_file: Vec<String>,
+
+    /// Apply the directives on top of the JSON document in FILE, or stdin if FILE is -
+    #[arg(long, value_name = "FILE")]
+    base: Option<String>,
+
+    /// Apply the directives to the JSON document in FILE and write the result back to FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "base")]
+    in_place: Option<String>,
+
+    /// With --in-place, sort keys and write compactly instead of keeping the key order and
+    /// indentation of FILE
+    #[arg(long, requires = "in_place")]
+    normalize: bool,
+
+    /// Ignore deletions of paths that do not exist
+    #[arg(long)]
+    ignore_missing: bool,
+
+    /// Write the output in another format than JSON
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = Format::Json)]
+    output_format: Format,
+
+    /// With --output-format cbor, use the core deterministic encoding of RFC 8949
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Write canonical JSON (RFC 8785)
+    #[arg(long, conflicts_with_all = ["in_place", "preserve_order", "pretty", "color"])]
+    canonical: bool,
+
+    /// Write object keys in the order of their first directive rather than sorted
+    #[arg(long, conflicts_with = "in_place")]
+    preserve_order: bool,
+
+    /// Write the output indented, with one member or element per line
+    #[arg(long, conflicts_with = "in_place")]
+    pretty: bool,
+
+    /// With --pretty, the indentation of each level of nesting: a number of spaces, or "tab"
+    #[arg(
+        long,
+        value_name = "N|tab",
+        default_value = "2",
+        value_parser = parse_indent,
+        requires = "pretty"
+    )]
+    indent: String,
+
+    /// Color keys, strings, numbers and literals in the output
+    #[arg(long, value_name = "WHEN", value_enum, default_value_t = When::Auto)]
+    color: When,
+
+    /// Escape all non-ASCII characters in keys and strings as \uXXXX
+    #[arg(long, conflicts_with_all = ["in_place", "canonical"])]
+    ascii: bool,
+
+    /// Escape <, >, &, U+2028 and U+2029 in keys and strings as \uXXXX
+    #[arg(long, conflicts_with_all = ["in_place", "canonical"])]
+    html_safe: bool,
+
+    /// Read a JSON document from stdin and print directives that compose it
+    #[arg(long, conflicts_with_all = ["DIRECTIVE", "stdin", "base", "in_place"])]
+    explode: bool,
+
+    /// Read records from stdin and print one document per record, with the directives as
+    /// templates where $1, $2 and so on stand for the fields of the record
+    #[arg(
+        long,
+        value_name = "csv|tsv",
+        value_parser = parse_record_format,
+        conflicts_with_all = ["stdin", "base", "in_place", "explode", "canonical", "pretty"]
+    )]
+    records: Option<RecordFormat>,
+
+    /// With --records, report bad records and skip them instead of stopping at the first one,
+    /// still exiting with status 2 if any record was skipped
+    #[arg(long, requires = "records")]
+    skip_bad_records: bool,
+
+    /// How to write the documents of directives separated by ,, [default: ndjson]
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_enum,
+        conflicts_with_all = ["stdin", "in_place", "records", "canonical"]
+    )]
+    documents: Option<Documents>,
+
+    /// How each output document is delimited: newline, json-seq, netstring, u32be, u32le or
+    /// none [default: newline, or none with other formats than JSON]
+    #[arg(long, value_name = "FRAME", conflicts_with_all = ["in_place", "explode"])]
+    frame: Option<Frame>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the paths where two JSON documents differ
+    Diff {
+        /// The old JSON document, or - for stdin
+        old: String,
+
+        /// The new JSON document, or - for stdin
+        new: String,
+
+        /// Print directives that turn OLD into NEW when applied with --base OLD
+        #[arg(long)]
+        directives: bool,
+    },
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, ValueEnum)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Cbor,
+    Msgpack,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq, ValueEnum)]
+enum Documents {
+    /// One document per line
+    Ndjson,
+    /// A JSON array of the documents
+    Array,
+    /// JSON text sequences (RFC 7464)
+    JsonSeq,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum When {
+    /// Color the output if stdout is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
 }
 
 fn main() -> ExitCode {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if args.output_format != Format::Json
+        && (args.canonical
+            || args.pretty
+            || args.ascii
+            || args.html_safe
+            || args.in_place.is_some()
+            || args.records.is_some())
+    {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--canonical, --pretty, --ascii, --html-safe, --in-place and --records require \
+                 JSON output",
+            )
+            .exit();
+    }
+    if args.deterministic && args.output_format != Format::Cbor {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--deterministic requires --output-format cbor",
+            )
+            .exit();
+    }
+
+    let inputs = match expand_args(std::mem::take(&mut args.directives).into_iter()) {
+        Ok(inputs) => inputs,
+        Err(message) => {
+            eprintln!("input error: {}", message);
+            return ExitCode::from(2);
+        }
+    };
+    let mut groups = split_documents(inputs);
+    let documents = args
+        .documents
+        .or((groups.len() > 1).then_some(Documents::Ndjson));
+    if documents.is_some()
+        && (args.stdin
+            || args.in_place.is_some()
+            || args.records.is_some()
+            || args.canonical
+            || args.output_format != Format::Json)
+    {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "several documents require JSON output, and cannot be combined with --stdin, \
+                 --in-place, --records or --canonical",
+            )
+            .exit();
+    }
+    let frame = args.frame.unwrap_or(match documents {
+        Some(Documents::JsonSeq) => Frame::JsonSeq,
+        _ if args.output_format != Format::Json => Frame::None,
+        _ => Frame::Newline,
+    });
+    if documents == Some(Documents::JsonSeq) && frame != Frame::JsonSeq {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--documents json-seq cannot be combined with another --frame than json-seq",
+            )
+            .exit();
+    }
+    if documents == Some(Documents::Ndjson) && frame == Frame::Newline && args.pretty {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--pretty cannot be combined with --documents ndjson and newline frames",
+            )
+            .exit();
+    }
+    let style = Style {
+        preserve_order: args.preserve_order,
+        indent: args.pretty.then(|| args.indent.clone()),
+        color: match args.color {
+            When::Auto => {
+                std::io::stdout().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+            When::Always => true,
+            When::Never => false,
+        },
+        ascii: args.ascii,
+        html_safe: args.html_safe,
+    };
+
+    if let Some(Command::Diff {
+        old,
+        new,
+        directives,
+    }) = &args.command
+    {
+        return run_diff(old, new, *directives);
+    }
+
+    if args.explode {
+        let node = match read_base("-") {
+            Ok((_, node)) => node,
+            Err(message) => {
+                eprintln!("input error: {}", message);
+                return ExitCode::from(2);
+            }
+        };
+        let directives: Vec<_> = explode(&node).iter().map(|d| shell_quote(d)).collect();
+        println!("{}", directives.join(" \\\n"));
+        return ExitCode::from(0);
+    }
 
-    match compose(args.directives.into_iter()) {
+    let file = args.in_place.as_deref().or(args.base.as_deref());
+    let (text, base) = match file.map(read_base).transpose() {
+        Ok(base) => base.unzip(),
+        Err(message) => {
+            eprintln!("input error: {}", message);
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut options = Options {
+        ignore_missing: args.ignore_missing,
+        ..Options::default()
+    };
+    for file in &args.vars_file {
+        if let Err(message) = read_vars_file(file, &mut options.variables) {
+            eprintln!("input error: {}", message);
+            return ExitCode::from(2);
+        }
+    }
+    options.variables.extend(args.vars);
+
+    if let Some(documents) = documents {
+        return run_documents(groups, base.as_ref(), &options, &style, documents, frame);
+    }
+
+    let mut inputs = groups.pop().expect("there is always a group");
+    if args.stdin {
+        let mut buffer = vec![];
+        if let Err(message) = std::io::stdin().read_to_end(&mut buffer) {
+            eprintln!("input error: reading stdin: {}", message);
+            return ExitCode::from(2);
+        }
+        inputs.extend(split_nul_separated(&buffer));
+    }
+
+    if let Some(format) = args.records {
+        return run_records(
+            format,
+            &inputs,
+            &options,
+            &style,
+            frame,
+            args.skip_bad_records,
+        );
+    }
+
+    let result = match &base {
+        Some(base) => apply(base, inputs.into_iter(), &options),
+        None => compose_with(inputs.into_iter(), &options),
+    };
+    match result {
         Ok(tree) => {
-            if let Some(node) = tree {
-                println!("{}", node);
+            if let (Some(file), Some(text)) = (&args.in_place, &text) {
+                let Some(node) = tree else {
+                    eprintln!(
+                        "input error: in-place: no document left to write to \"{}\"",
+                        file
+                    );
+                    return ExitCode::from(2);
+                };
+                let style = if args.normalize {
+                    Style::default()
+                } else {
+                    Style::of_document(text)
+                };
+                let mut json = to_json(&node, &style);
+                if text.ends_with('\n') {
+                    json.push('\n');
+                }
+                if let Err(message) = write_atomically(file, &json) {
+                    eprintln!("output error: writing \"{}\": {}", file, message);
+                    return ExitCode::from(1);
+                }
+            } else if let Some(node) = tree.as_ref().filter(|_| args.canonical) {
+                match to_canonical_json(node) {
+                    Ok(json) => {
+                        let stdout = &mut std::io::stdout().lock();
+                        if let Err(message) = write_framed(stdout, json.as_bytes(), frame) {
+                            eprintln!("output error: {}", message);
+                            return ExitCode::from(1);
+                        }
+                    }
+                    Err(message) => {
+                        eprintln!("input error: {}", message);
+                        return ExitCode::from(2);
+                    }
+                }
+            } else if let Some(node) = tree.as_ref().filter(|_| args.output_format != Format::Json)
+            {
+                let style = Style {
+                    preserve_order: args.preserve_order,
+                    ..Style::default()
+                };
+                let output = match args.output_format {
+                    Format::Yaml => Ok(to_yaml(node, &style).into_bytes()),
+                    Format::Toml => to_toml(node, &style)
+                        .map(String::into_bytes)
+                        .map_err(|e| e.to_string()),
+                    Format::Cbor => {
+                        to_cbor(node, &style, args.deterministic).map_err(|e| e.to_string())
+                    }
+                    Format::Msgpack => to_msgpack(node, &style).map_err(|e| e.to_string()),
+                    Format::Json => unreachable!("JSON is written below"),
+                };
+                match output {
+                    Ok(bytes) => {
+                        let stdout = &mut std::io::stdout().lock();
+                        if let Err(message) = write_framed(stdout, &bytes, frame) {
+                            eprintln!("output error: {}", message);
+                            return ExitCode::from(1);
+                        }
+                    }
+                    Err(message) => {
+                        eprintln!("input error: {}", message);
+                        return ExitCode::from(2);
+                    }
+                }
+            } else if let Some(node) = tree {
+                let json = to_json(&node, &style);
+                if let Err(message) =
+                    write_framed(&mut std::io::stdout().lock(), json.as_bytes(), frame)
+                {
+                    eprintln!("output error: {}", message);
+                    return ExitCode::from(1);
+                }
             }
             ExitCode::from(0)
         }
@@ -27,3 +442,196 @@ fn main() -> ExitCode {
         }
     }
 }
+
+fn parse_var(input: &str) -> Result<(String, String), String> {
+    parse_variable_definition(input).ok_or_else(|| "must be of the form NAME=VALUE".to_string())
+}
+
+/// Compares two documents, exiting with 0 if they are the same and 1 if they differ.
+fn run_diff(old: &str, new: &str, directives: bool) -> ExitCode {
+    let (old, new) = match read_base(old).and_then(|old| Ok((old, read_base(new)?))) {
+        Ok(((_, old), (_, new))) => (old, new),
+        Err(message) => {
+            eprintln!("input error: {}", message);
+            return ExitCode::from(2);
+        }
+    };
+    let changes = diff(&old, &new);
+    if directives {
+        let directives: Vec<_> = to_directives(&changes)
+            .iter()
+            .map(|d| shell_quote(d))
+            .collect();
+        if !directives.is_empty() {
+            println!("{}", directives.join(" \\\n"));
+        }
+    } else {
+        for line in to_report(&changes) {
+            println!("{}", line);
+        }
+    }
+    ExitCode::from(if changes.is_empty() { 0 } else { 1 })
+}
+
+/// Composes one document per group of directives and prints them all in the given format.
+///
+/// Nothing is printed unless every document is composed successfully.
+fn run_documents(
+    groups: Vec<Vec<Input>>,
+    base: Option<&Node>,
+    options: &Options,
+    style: &Style,
+    documents: Documents,
+    frame: Frame,
+) -> ExitCode {
+    let mut nodes = vec![];
+    for (i, group) in groups.into_iter().enumerate() {
+        let document = i + 1;
+        let result = match base {
+            Some(base) => apply(base, group.into_iter(), options),
+            None => compose_with(group.into_iter(), options),
+        };
+        match result {
+            Ok(Some(node)) => nodes.push(node),
+            Ok(None) => {
+                eprintln!("input error: document {}: no document to write", document);
+                return ExitCode::from(2);
+            }
+            Err(message) => {
+                eprintln!("input error: document {}: {}", document, message);
+                return ExitCode::from(2);
+            }
+        }
+    }
+    if documents == Documents::Array {
+        let array = nodes
+            .into_iter()
+            .enumerate()
+            .map(|(i, node)| (i as u32, node))
+            .collect();
+        nodes = vec![Node::Array(array)];
+    }
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    for node in &nodes {
+        if let Err(message) = write_framed(&mut stdout, to_json(node, style).as_bytes(), frame) {
+            eprintln!("output error: {}", message);
+            return ExitCode::from(1);
+        }
+    }
+    if let Err(message) = stdout.flush() {
+        eprintln!("output error: {}", message);
+        return ExitCode::from(1);
+    }
+    ExitCode::from(0)
+}
+
+/// Composes one document per record read from stdin and prints it in a frame of its own.
+///
+/// Bad records either stop the run or are reported and skipped, in which case the run still ends
+/// with an input error.
+fn run_records(
+    format: RecordFormat,
+    templates: &[Input],
+    options: &Options,
+    style: &Style,
+    frame: Frame,
+    skip_bad_records: bool,
+) -> ExitCode {
+    if let Err(message) = check_templates(templates) {
+        eprintln!("input error: {}", message);
+        return ExitCode::from(2);
+    }
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    let mut records = Records::new(std::io::stdin().lock(), format);
+    let mut skipped = false;
+    while let Some(fields) = records.next() {
+        let record = records.last_record();
+        let result = fields.and_then(|fields| compose_record(templates, &fields, record, options));
+        let json = match result {
+            Ok(Some(node)) => to_json(&node, style),
+            Ok(None) => continue,
+            Err(message) => {
+                eprintln!("input error: {}", message);
+                if skip_bad_records && !matches!(message, RecordError::ReadRecords { .. }) {
+                    skipped = true;
+                    continue;
+                }
+                return ExitCode::from(2);
+            }
+        };
+        if let Err(message) = write_framed(&mut stdout, json.as_bytes(), frame) {
+            eprintln!("output error: {}", message);
+            return ExitCode::from(1);
+        }
+    }
+    if let Err(message) = stdout.flush() {
+        eprintln!("output error: {}", message);
+        return ExitCode::from(1);
+    }
+    ExitCode::from(if skipped { 2 } else { 0 })
+}
+
+/// Quotes an argument for POSIX shells, unless it only contains characters that need no quoting.
+fn shell_quote(arg: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-.,:=+/%".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+fn parse_record_format(input: &str) -> Result<RecordFormat, String> {
+    match input {
+        "csv" => Ok(RecordFormat::Csv),
+        "tsv" => Ok(RecordFormat::Tsv),
+        _ => Err("must be \"csv\" or \"tsv\"".to_string()),
+    }
+}
+
+fn parse_indent(input: &str) -> Result<String, String> {
+    match input {
+        "tab" => Ok("\t".to_string()),
+        _ => match input.parse::<u8>() {
+            Ok(n) if n <= 16 => Ok(" ".repeat(n.into())),
+            _ => Err("must be a number of spaces from 0 to 16, or \"tab\"".to_string()),
+        },
+    }
+}
+
+fn read_base(file: &str) -> Result<(String, Node), String> {
+    let mut text = String::new();
+    if file == "-" {
+        std::io::stdin().read_to_string(&mut text)
+    } else {
+        std::fs::File::open(file).and_then(|mut f| f.read_to_string(&mut text))
+    }
+    .map_err(|e| format!("base document \"{}\": {}", file, e))?;
+    let node = text
+        .parse()
+        .map_err(|e| format!("base document \"{}\": {}", file, e))?;
+    Ok((text, node))
+}
+
+/// Replaces the contents of a file by writing a temporary file next to it and renaming it.
+///
+/// Readers of the file see either the old or the new contents, never a partial write.
+/// Symbolic links are followed, so that the file they point to is replaced rather than the link.
+fn write_atomically(file: &str, contents: &str) -> std::io::Result<()> {
+    let path = &std::fs::canonicalize(file)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.mkjson-{}", name, std::process::id()));
+    let permissions = std::fs::metadata(path)?.permissions();
+    let result = std::fs::File::create(&temp).and_then(|mut f| {
+        f.write_all(contents.as_bytes())?;
+        f.set_permissions(permissions)?;
+        f.sync_all()
+    });
+    match result.and_then(|_| std::fs::rename(&temp, path)) {
+        Ok(()) => Ok(()),
+        Err(error) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(error)
+        }
+    }
+}
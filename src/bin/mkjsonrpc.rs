@@ -1,8 +1,18 @@
 use clap::Parser;
-use mkjson::composer::compose;
+use mkjson::composer::Options;
+use mkjson::composer::compose_with;
+use mkjson::input::expand_args;
+use mkjson::input::parse_variable_definition;
+use mkjson::input::read_vars_file;
+use mkjson::input::split_nul_separated;
 use mkjson::node::Node;
+use mkjson::output::Frame;
+use mkjson::output::Style;
+use mkjson::output::to_json;
+use mkjson::output::write_framed;
 use mkjson::parser::is_xid_string;
 use mkjson::parser::validate_json;
+use std::io::Read;
 use std::process::ExitCode;
 use std::rc::Rc;
 
@@ -18,32 +28,88 @@ struct Args {
     #[arg(short, long, value_parser = validate_method)]
     method: String,
 
-    /// "params" directives (e.g., a.b:true c.0.d=foobar)
+    /// "params" directives (e.g., a.b:true c.0.d=foobar), or @FILE to read directives from a file
     #[arg(id = "DIRECTIVE")]
     directives: Vec<Vec<u8>>,
+
+    /// Also read NUL-separated "params" directives from stdin
+    #[arg(short = '0', long)]
+    stdin: bool,
+
+    /// Define a variable for interpolating string directives (e.g., host=example.com)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    vars: Vec<(String, String)>,
+
+    /// Read variable definitions from a file, one NAME=VALUE per line
+    #[arg(long, value_name = "FILE")]
+    vars_file: Vec<String>,
+
+    /// Write "jsonrpc", "method", "params" and "id" in that order, and the keys of "params" in
+    /// the order of their first directive, rather than sorted
+    #[arg(long)]
+    preserve_order: bool,
+
+    /// How the request is delimited: newline, json-seq, netstring, u32be, u32le or none
+    #[arg(long, value_name = "FRAME", default_value = "newline")]
+    frame: Frame,
 }
 
 fn main() -> ExitCode {
     let args = Args::parse();
 
-    match compose(args.directives.into_iter()) {
+    let mut inputs = match expand_args(args.directives.into_iter()) {
+        Ok(inputs) => inputs,
+        Err(message) => {
+            eprintln!("input error: {}", message);
+            return ExitCode::from(2);
+        }
+    };
+    if args.stdin {
+        let mut buffer = vec![];
+        if let Err(message) = std::io::stdin().read_to_end(&mut buffer) {
+            eprintln!("input error: reading stdin: {}", message);
+            return ExitCode::from(2);
+        }
+        inputs.extend(split_nul_separated(&buffer));
+    }
+
+    let mut options = Options::default();
+    for file in &args.vars_file {
+        if let Err(message) = read_vars_file(file, &mut options.variables) {
+            eprintln!("input error: {}", message);
+            return ExitCode::from(2);
+        }
+    }
+    options.variables.extend(args.vars);
+
+    match compose_with(inputs.into_iter(), &options) {
         Ok(tree) => {
             let mut attributes = vec![
                 (
-                    Rc::new("\"jsonrpc\"".to_string()),
+                    Rc::new("jsonrpc".to_string()),
                     Node::Value("\"2.0\"".to_string()),
                 ),
-                (Rc::new("\"method\"".to_string()), Node::Value(args.method)),
+                (Rc::new("method".to_string()), Node::Value(args.method)),
             ];
-            if args.id != ":omit" {
-                attributes.push((Rc::new("\"id\"".to_string()), Node::Value(args.id)));
-            }
             if let Some(node) = tree {
-                attributes.push((Rc::new("\"params\"".to_string()), node));
+                attributes.push((Rc::new("params".to_string()), node));
+            }
+            if args.id != ":omit" {
+                attributes.push((Rc::new("id".to_string()), Node::Value(args.id)));
             }
             let request = Node::Object(attributes.into_iter().collect());
+            let style = Style {
+                preserve_order: args.preserve_order,
+                ..Style::default()
+            };
 
-            println!("{}", request);
+            let json = to_json(&request, &style);
+            if let Err(message) =
+                write_framed(&mut std::io::stdout().lock(), json.as_bytes(), args.frame)
+            {
+                eprintln!("output error: {}", message);
+                return ExitCode::from(1);
+            }
 
             ExitCode::from(0)
         }
@@ -79,3 +145,7 @@ fn validate_id(input: &str) -> Result<String, String> {
         Err("must be a string, number, ':null' or ':omit'".to_string())
     }
 }
+
+fn parse_var(input: &str) -> Result<(String, String), String> {
+    parse_variable_definition(input).ok_or_else(|| "must be of the form NAME=VALUE".to_string())
+}
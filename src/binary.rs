@@ -0,0 +1,492 @@
+use crate::directive::Path;
+use crate::directive::Segment;
+use crate::directive::decode_string;
+use crate::node::Node;
+use crate::output::Style;
+use crate::output::canonical_number;
+use crate::output::members;
+use snafu::prelude::*;
+use std::rc::Rc;
+
+#[derive(Debug, Snafu)]
+pub enum BinaryError {
+    #[snafu(display("path {path}: number {number} is out of range for {format}"))]
+    NumberOutOfRange {
+        path: Rc<Path>,
+        number: String,
+        format: &'static str,
+    },
+
+    #[snafu(display(
+        "path {path}: number {number} differs from the shortest form of the nearest double"
+    ))]
+    InexactNumber { path: Rc<Path>, number: String },
+}
+
+type BinaryResult<T> = Result<T, BinaryError>;
+
+/// Writes a tree as CBOR (RFC 8949).
+///
+/// Integers are written in their shortest form, and integers outside the 64-bit range as
+/// bignums.
+/// Other numbers are written as double-precision floats, and rejected unless the shortest form
+/// of the nearest double has the same decimal value, as with canonical JSON.
+///
+/// With `deterministic`, the core deterministic encoding requirements are followed: floats are
+/// written in their shortest form, and map keys are sorted by their encoded bytes instead of as
+/// the style asks for.
+pub fn to_cbor(node: &Node, style: &Style, deterministic: bool) -> BinaryResult<Vec<u8>> {
+    let mut out = vec![];
+    write_cbor(&mut out, node, style, deterministic, &Path::root())?;
+    Ok(out)
+}
+
+fn write_cbor(
+    out: &mut Vec<u8>,
+    node: &Node,
+    style: &Style,
+    deterministic: bool,
+    path: &Rc<Path>,
+) -> BinaryResult<()> {
+    match node {
+        Node::Value(value) => match value.as_str() {
+            "false" => out.push(0xf4),
+            "true" => out.push(0xf5),
+            "null" => out.push(0xf6),
+            "[]" => write_cbor_head(out, 4, 0),
+            "{}" => write_cbor_head(out, 5, 0),
+            _ if value.starts_with('"') => {
+                write_cbor_text(out, &decode_string(&value[1..value.len() - 1]))
+            }
+            _ => match parse_number(value, path)? {
+                Number::Integer(negative, magnitude) => {
+                    // Negative integers are written as -1 - n
+                    let (major, tag, n) = if negative && magnitude.iter().any(|b| *b != 0) {
+                        (1, 3, decrement(&magnitude))
+                    } else {
+                        (0, 2, magnitude)
+                    };
+                    match to_u64(&n) {
+                        Some(n) => write_cbor_head(out, major, n),
+                        None => {
+                            write_cbor_head(out, 6, tag);
+                            write_cbor_head(out, 2, n.len() as u64);
+                            out.extend(n);
+                        }
+                    }
+                }
+                Number::Float(float) if deterministic => {
+                    let single = float as f32;
+                    if let Some(half) = to_half(single).filter(|_| single as f64 == float) {
+                        out.push(0xf9);
+                        out.extend(half.to_be_bytes());
+                    } else if single as f64 == float {
+                        out.push(0xfa);
+                        out.extend(single.to_be_bytes());
+                    } else {
+                        out.push(0xfb);
+                        out.extend(float.to_be_bytes());
+                    }
+                }
+                Number::Float(float) => {
+                    out.push(0xfb);
+                    out.extend(float.to_be_bytes());
+                }
+            },
+        },
+        Node::Array(array) => {
+            write_cbor_head(out, 4, array.len() as u64);
+            for (index, element) in array {
+                let path = path.append(Segment::Index(*index));
+                write_cbor(out, element, style, deterministic, &path)?;
+            }
+        }
+        Node::Object(object) => {
+            let mut entries = vec![];
+            for (key, member) in members(object, style) {
+                let mut encoded = vec![];
+                write_cbor_text(&mut encoded, &decode_string(key));
+                let path = path.append(Segment::Key(key.clone()));
+                let key_len = encoded.len();
+                write_cbor(&mut encoded, member, style, deterministic, &path)?;
+                entries.push((key_len, encoded));
+            }
+            if deterministic {
+                entries.sort_by(|(a_len, a), (b_len, b)| a[..*a_len].cmp(&b[..*b_len]));
+            }
+            write_cbor_head(out, 5, entries.len() as u64);
+            for (_, encoded) in entries {
+                out.extend(encoded);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_cbor_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if let Ok(n) = u8::try_from(n) {
+        out.push(major | 24);
+        out.push(n);
+    } else if let Ok(n) = u16::try_from(n) {
+        out.push(major | 25);
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = u32::try_from(n) {
+        out.push(major | 26);
+        out.extend(n.to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend(n.to_be_bytes());
+    }
+}
+
+fn write_cbor_text(out: &mut Vec<u8>, text: &str) {
+    write_cbor_head(out, 3, text.len() as u64);
+    out.extend(text.as_bytes());
+}
+
+/// Returns the half-precision bits of a float, if it has an exact half-precision
+/// representation.
+fn to_half(float: f32) -> Option<u16> {
+    let bits = float.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127;
+    let mantissa = bits & 0x7f_ffff;
+    if bits & 0x7fff_ffff == 0 {
+        Some(sign)
+    } else if (-14..=15).contains(&exponent) {
+        (mantissa & 0x1fff == 0)
+            .then(|| sign | (((exponent + 15) as u16) << 10) | (mantissa >> 13) as u16)
+    } else if (-24..-14).contains(&exponent) {
+        // Subnormal halves are multiples of 2^-24
+        let shift = -exponent - 1;
+        let significand = mantissa | 0x80_0000;
+        (significand & ((1 << shift) - 1) == 0).then(|| sign | (significand >> shift) as u16)
+    } else {
+        None
+    }
+}
+
+/// Writes a tree as MessagePack.
+///
+/// Integers are written in their shortest form, and integers outside the 64-bit range are
+/// rejected.
+/// Other numbers are written as double-precision floats, and rejected unless the shortest form
+/// of the nearest double has the same decimal value, as with canonical JSON.
+pub fn to_msgpack(node: &Node, style: &Style) -> BinaryResult<Vec<u8>> {
+    let mut out = vec![];
+    write_msgpack(&mut out, node, style, &Path::root())?;
+    Ok(out)
+}
+
+fn write_msgpack(
+    out: &mut Vec<u8>,
+    node: &Node,
+    style: &Style,
+    path: &Rc<Path>,
+) -> BinaryResult<()> {
+    match node {
+        Node::Value(value) => match value.as_str() {
+            "null" => out.push(0xc0),
+            "false" => out.push(0xc2),
+            "true" => out.push(0xc3),
+            "[]" => out.push(0x90),
+            "{}" => out.push(0x80),
+            _ if value.starts_with('"') => {
+                write_msgpack_str(out, &decode_string(&value[1..value.len() - 1]))
+            }
+            _ => match parse_number(value, path)? {
+                Number::Integer(negative, magnitude) => {
+                    let integer = to_u64(&magnitude).and_then(|n| {
+                        if negative {
+                            0i64.checked_sub_unsigned(n).map(i128::from)
+                        } else {
+                            Some(n.into())
+                        }
+                    });
+                    let integer = integer.context(NumberOutOfRangeSnafu {
+                        path: path.clone(),
+                        number: value,
+                        format: "MessagePack",
+                    })?;
+                    write_msgpack_int(out, integer);
+                }
+                Number::Float(float) => {
+                    out.push(0xcb);
+                    out.extend(float.to_be_bytes());
+                }
+            },
+        },
+        Node::Array(array) => {
+            write_msgpack_head(out, array.len(), 0x90);
+            for (index, element) in array {
+                write_msgpack(out, element, style, &path.append(Segment::Index(*index)))?;
+            }
+        }
+        Node::Object(object) => {
+            write_msgpack_head(out, object.len(), 0x80);
+            for (key, member) in members(object, style) {
+                write_msgpack_str(out, &decode_string(key));
+                write_msgpack(out, member, style, &path.append(Segment::Key(key.clone())))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_msgpack_int(out: &mut Vec<u8>, integer: i128) {
+    if (-32..0x80).contains(&integer) {
+        // Positive and negative fixints
+        out.extend((integer as i8).to_be_bytes());
+    } else if let Ok(n) = u8::try_from(integer) {
+        out.push(0xcc);
+        out.push(n);
+    } else if let Ok(n) = u16::try_from(integer) {
+        out.push(0xcd);
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = u32::try_from(integer) {
+        out.push(0xce);
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = u64::try_from(integer) {
+        out.push(0xcf);
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = i8::try_from(integer) {
+        out.push(0xd0);
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = i16::try_from(integer) {
+        out.push(0xd1);
+        out.extend(n.to_be_bytes());
+    } else if let Ok(n) = i32::try_from(integer) {
+        out.push(0xd2);
+        out.extend(n.to_be_bytes());
+    } else {
+        let n = i64::try_from(integer).expect("caller is responsible for the range");
+        out.push(0xd3);
+        out.extend(n.to_be_bytes());
+    }
+}
+
+fn write_msgpack_str(out: &mut Vec<u8>, text: &str) {
+    let len = text.len();
+    if len < 32 {
+        out.push(0xa0 | len as u8);
+    } else if let Ok(n) = u8::try_from(len) {
+        out.push(0xd9);
+        out.push(n);
+    } else if let Ok(n) = u16::try_from(len) {
+        out.push(0xda);
+        out.extend(n.to_be_bytes());
+    } else {
+        out.push(0xdb);
+        out.extend((len as u32).to_be_bytes());
+    }
+    out.extend(text.as_bytes());
+}
+
+/// Writes the number of elements of an array (fixarray `0x90`) or members of a map (fixmap
+/// `0x80`).
+fn write_msgpack_head(out: &mut Vec<u8>, len: usize, fixed: u8) {
+    // The 16-bit and 32-bit forms of arrays and maps follow each other
+    let marker16 = if fixed == 0x90 { 0xdc } else { 0xde };
+    if len < 16 {
+        out.push(fixed | len as u8);
+    } else if let Ok(n) = u16::try_from(len) {
+        out.push(marker16);
+        out.extend(n.to_be_bytes());
+    } else {
+        out.push(marker16 + 1);
+        out.extend((len as u32).to_be_bytes());
+    }
+}
+
+enum Number {
+    /// The sign and the big-endian bytes of the magnitude of an integer
+    Integer(bool, Vec<u8>),
+    Float(f64),
+}
+
+fn parse_number(value: &str, path: &Rc<Path>) -> BinaryResult<Number> {
+    if !value.contains(['.', 'e', 'E']) {
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, value),
+        };
+        return Ok(Number::Integer(negative, decimal_to_bytes(digits)));
+    }
+    let float: f64 = value.parse().expect("JSON numbers are valid floats");
+    ensure!(
+        float.is_finite(),
+        NumberOutOfRangeSnafu {
+            path: path.clone(),
+            number: value,
+            format: "a double",
+        }
+    );
+    ensure!(
+        canonical_number(value).is_some(),
+        InexactNumberSnafu {
+            path: path.clone(),
+            number: value,
+        }
+    );
+    Ok(Number::Float(float))
+}
+
+/// Converts decimal digits into big-endian bytes without leading zeros.
+fn decimal_to_bytes(digits: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = vec![];
+    for digit in digits.bytes() {
+        let mut carry = u32::from(digit - b'0');
+        for byte in bytes.iter_mut().rev() {
+            let product = u32::from(*byte) * 10 + carry;
+            *byte = product as u8;
+            carry = product >> 8;
+        }
+        if carry > 0 {
+            bytes.insert(0, carry as u8);
+        }
+    }
+    bytes
+}
+
+/// Subtracts one from a non-zero big-endian number, removing any new leading zero.
+fn decrement(bytes: &[u8]) -> Vec<u8> {
+    let mut bytes = bytes.to_vec();
+    for byte in bytes.iter_mut().rev() {
+        let (difference, borrow) = byte.overflowing_sub(1);
+        *byte = difference;
+        if !borrow {
+            break;
+        }
+    }
+    if bytes.first() == Some(&0) {
+        bytes.remove(0);
+    }
+    bytes
+}
+
+fn to_u64(bytes: &[u8]) -> Option<u64> {
+    (bytes.len() <= 8).then(|| bytes.iter().fold(0, |n, byte| (n << 8) | u64::from(*byte)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn cbor(document: &str, deterministic: bool) -> BinaryResult<Vec<u8>> {
+        to_cbor(&document.parse().unwrap(), &Style::default(), deterministic)
+    }
+
+    fn msgpack(document: &str) -> BinaryResult<Vec<u8>> {
+        to_msgpack(&document.parse().unwrap(), &Style::default())
+    }
+
+    #[test]
+    fn write_cbor_integers_in_shortest_form() {
+        assert_eq!(
+            cbor("[0,23,24,-1,-25,256,-0]", false).unwrap(),
+            [
+                0x87, 0x00, 0x17, 0x18, 0x18, 0x20, 0x38, 0x18, 0x19, 0x01, 0x00, 0x00
+            ]
+        );
+        assert_eq!(
+            cbor("18446744073709551615", false).unwrap(),
+            [0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(
+            cbor("18446744073709551616", false).unwrap(),
+            [0xc2, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            cbor("-18446744073709551617", false).unwrap(),
+            [0xc3, 0x49, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            cbor("-18446744073709551616", false).unwrap(),
+            [0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn write_cbor_floats() {
+        assert_eq!(
+            cbor("1.5", false).unwrap(),
+            [0xfb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(cbor("1.5", true).unwrap(), [0xf9, 0x3e, 0x00]);
+        assert_eq!(cbor("-0.0", true).unwrap(), [0xf9, 0x80, 0x00]);
+        assert_eq!(
+            cbor("5.960464477539063e-8", true).unwrap(),
+            [0xf9, 0x00, 0x01]
+        );
+        assert_eq!(
+            cbor("100000.0", true).unwrap(),
+            [0xfa, 0x47, 0xc3, 0x50, 0x00]
+        );
+        assert_eq!(
+            cbor("1.1", true).unwrap(),
+            [0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a]
+        );
+        assert_matches!(
+            cbor("[1e400]", false),
+            Err(BinaryError::NumberOutOfRange { path, .. }) if path.to_string() == "0"
+        );
+        assert_matches!(
+            cbor("0.30000000000000001", false),
+            Err(BinaryError::InexactNumber { .. })
+        );
+    }
+
+    #[test]
+    fn write_cbor_maps_and_strings() {
+        let document = r#"{"bb":"\u00e9","a":[true,false,null,{}],"c":[]}"#;
+        assert_eq!(
+            cbor(document, false).unwrap(),
+            [
+                0xa3, 0x61, b'a', 0x84, 0xf5, 0xf4, 0xf6, 0xa0, 0x62, b'b', b'b', 0x62, 0xc3, 0xa9,
+                0x61, b'c', 0x80
+            ]
+        );
+        // Deterministic encoding sorts shorter keys first
+        assert_eq!(
+            cbor(document, true).unwrap(),
+            [
+                0xa3, 0x61, b'a', 0x84, 0xf5, 0xf4, 0xf6, 0xa0, 0x61, b'c', 0x80, 0x62, b'b', b'b',
+                0x62, 0xc3, 0xa9
+            ]
+        );
+    }
+
+    #[test]
+    fn write_msgpack_values() {
+        assert_eq!(
+            msgpack("[127,128,-32,-33,256,-129,65536,-32769]").unwrap(),
+            [
+                0x98, 0x7f, 0xcc, 0x80, 0xe0, 0xd0, 0xdf, 0xcd, 0x01, 0x00, 0xd1, 0xff, 0x7f, 0xce,
+                0x00, 0x01, 0x00, 0x00, 0xd2, 0xff, 0xff, 0x7f, 0xff
+            ]
+        );
+        assert_eq!(
+            msgpack(r#"{"\u00e9":[null,true,1.5,"x"]}"#).unwrap(),
+            [
+                0x81, 0xa2, 0xc3, 0xa9, 0x94, 0xc0, 0xc3, 0xcb, 0x3f, 0xf8, 0, 0, 0, 0, 0, 0, 0xa1,
+                b'x'
+            ]
+        );
+        assert_eq!(
+            msgpack("-9223372036854775808").unwrap(),
+            [0xd3, 0x80, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_matches!(
+            msgpack("18446744073709551616"),
+            Err(BinaryError::NumberOutOfRange { number, .. }) if number == "18446744073709551616"
+        );
+        assert_matches!(
+            msgpack("-9223372036854775809"),
+            Err(BinaryError::NumberOutOfRange { .. })
+        );
+    }
+}
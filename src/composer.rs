@@ -1,10 +1,35 @@
+use crate::directive::Directive;
+use crate::directive::IndexAllocator;
+use crate::directive::Path;
+use crate::directive::Segment;
+use crate::directive::encode_hex;
+use crate::directive::expand_path;
+use crate::input::Input;
+use crate::input::Location;
 use crate::node::Node;
 use crate::node::build_tree;
+use crate::parser::DirectiveAst;
+use crate::parser::MAX_PATHS;
+use crate::parser::OperatorAst;
+use crate::parser::SegmentAst;
+use crate::parser::SourceAst;
+use crate::parser::StatementAst;
 use crate::parser::SyntaxError;
-use crate::parser::parse_directive;
+use crate::parser::TemplatePartAst;
+use crate::parser::count_paths;
+use crate::parser::parse_complete_path;
+use crate::parser::parse_statement;
+use crate::parser::parse_template;
+use crate::parser::validate_value;
+use crate::validator::NodeKind;
 use crate::validator::PathError;
+use crate::validator::PathErrorVariant;
 use crate::validator::validate;
 use snafu::prelude::*;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::Read;
+use std::rc::Rc;
 use std::str::Utf8Error;
 use unicode_general_category::GeneralCategory;
 use unicode_general_category::get_general_category;
@@ -23,8 +48,83 @@ pub enum BuildError {
         directive: String,
     },
 
-    #[snafu(display("validating: {source}"))]
-    Path { source: PathError },
+    #[snafu(display("directive \"{directive}\": reading \"{file}\": {source}"))]
+    ReadFile {
+        source: std::io::Error,
+        directive: String,
+        file: String,
+    },
+
+    #[snafu(display("directive \"{directive}\": file \"{file}\": {source}"))]
+    FileEncoding {
+        source: Utf8Error,
+        directive: String,
+        file: String,
+    },
+
+    #[snafu(display("directive \"{directive}\": file \"{file}\": {source}"))]
+    FileSyntax {
+        source: SyntaxError,
+        directive: String,
+        file: String,
+    },
+
+    #[snafu(display("directive \"{directive}\": environment variable {variable} is not set"))]
+    MissingVariable { directive: String, variable: String },
+
+    #[snafu(display(
+        "directive \"{directive}\": environment variable {variable}: invalid UTF-8 in \"{value}\""
+    ))]
+    VariableEncoding {
+        directive: String,
+        variable: String,
+        value: String,
+    },
+
+    #[snafu(display("directive \"{directive}\": environment variable {variable}: {source}"))]
+    VariableSyntax {
+        source: SyntaxError,
+        directive: String,
+        variable: String,
+    },
+
+    #[snafu(display("directive \"{directive}\": variable {variable} is not defined"))]
+    UndefinedVariable { directive: String, variable: String },
+
+    #[snafu(display("directive \"{directive}\": {source}"))]
+    Resolve {
+        source: PathError,
+        directive: String,
+    },
+
+    #[snafu(display("directive \"{directive}\": source path must not expand into several paths"))]
+    AmbiguousSource { directive: String },
+
+    #[snafu(display(
+        "directive \"{directive}\": deletions, moves and copies must precede assignments"
+    ))]
+    EditAfterAssignment { directive: String },
+
+    #[snafu(display("directive \"{directive}\": no group to close"))]
+    UnbalancedGroup { directive: String },
+
+    #[snafu(display("directive \"{directive}\": group is never closed"))]
+    UnclosedGroup { directive: String },
+
+    #[snafu(display("directive \"{directive}\": {source}"))]
+    Path {
+        source: PathError,
+        directive: String,
+    },
+
+    #[snafu(display("base document: {source}"))]
+    Base { source: PathError },
+
+    #[snafu(display("{location}: {source}"))]
+    Located {
+        location: Location,
+        source: Box<BuildError>,
+    },
 }
 
 type BuildResult<T> = Result<T, BuildError>;
@@ -42,7 +142,7 @@ fn should_escape(c: char) -> bool {
 
 fn safe_bytes_display(bytes: &[u8]) -> String {
     bytes
-        .into_iter()
+        .iter()
         .cloned()
         .map(|b| match b {
             b'"' => r#"\""#.to_string(),
@@ -73,21 +173,546 @@ fn safe_unicode_display(chars: &str) -> String {
         .collect()
 }
 
-pub fn compose<'a>(inputs: impl Iterator<Item = Vec<u8>>) -> BuildResult<Option<Node>> {
-    let mut directives = vec![];
-    for bytes in inputs {
-        let text = str::from_utf8(&bytes).context(EncodingSnafu {
-            directive: safe_bytes_display(&bytes),
+/// Replaces the file name of a file-sourced directive with the contents of the file.
+///
+/// Binary contents are hex encoded, just like the inline value of a binary directive.
+/// Trailing whitespace, such as the final newline of a file, is dropped from the contents of a
+/// typed directive.
+///
+/// The file name `-` refers to stdin, which is only read once no matter how many directives refer
+/// to it.
+fn load_file(ast: &mut DirectiveAst, stdin: &mut Option<Vec<u8>>, text: &str) -> BuildResult<()> {
+    let file = std::mem::take(&mut ast.value);
+    let bytes = if file == "-" {
+        if stdin.is_none() {
+            let mut buffer = vec![];
+            std::io::stdin()
+                .read_to_end(&mut buffer)
+                .context(ReadFileSnafu {
+                    directive: safe_unicode_display(text),
+                    file: file.clone(),
+                })?;
+            *stdin = Some(buffer);
+        }
+        stdin.clone().expect("stdin has been read")
+    } else {
+        std::fs::read(&file).context(ReadFileSnafu {
+            directive: safe_unicode_display(text),
+            file: safe_unicode_display(&file),
+        })?
+    };
+    ast.source = SourceAst::Inline;
+    ast.value = if ast.operator.is_binary() {
+        encode_hex(&bytes)
+    } else {
+        let mut content = String::from_utf8(bytes)
+            .map_err(|e| e.utf8_error())
+            .context(FileEncodingSnafu {
+                directive: safe_unicode_display(text),
+                file: safe_unicode_display(&file),
+            })?;
+        if ast.operator.is_typed() {
+            content.truncate(
+                content
+                    .trim_end_matches(|c: char| c.is_ascii_whitespace())
+                    .len(),
+            );
+        }
+        validate_value(ast.operator, 1, &content).context(FileSyntaxSnafu {
+            directive: safe_unicode_display(text),
+            file: safe_unicode_display(&file),
+        })?;
+        content
+    };
+    Ok(())
+}
+
+/// Replaces the name of an environment variable with its value.
+fn load_variable(ast: &mut DirectiveAst, text: &str) -> BuildResult<()> {
+    let variable = std::mem::take(&mut ast.value);
+    let value = std::env::var_os(&variable).context(MissingVariableSnafu {
+        directive: safe_unicode_display(text),
+        variable: safe_unicode_display(&variable),
+    })?;
+    let content = value
+        .into_string()
+        .map_err(|value| BuildError::VariableEncoding {
+            directive: safe_unicode_display(text),
+            variable: safe_unicode_display(&variable),
+            value: safe_bytes_display(value.as_encoded_bytes()),
+        })?;
+    ast.source = SourceAst::Inline;
+    ast.value = if ast.operator.is_binary() {
+        encode_hex(content.as_bytes())
+    } else {
+        validate_value(ast.operator, 1, &content).context(VariableSyntaxSnafu {
+            directive: safe_unicode_display(text),
+            variable: safe_unicode_display(&variable),
+        })?;
+        content
+    };
+    Ok(())
+}
+
+/// Replaces the variable references of an interpolating string directive with the values of the
+/// variables, turning it into a plain string directive.
+fn interpolate(
+    ast: &mut DirectiveAst,
+    variables: &HashMap<String, String>,
+    text: &str,
+) -> BuildResult<()> {
+    let (parts, _, _) = parse_template(1, &ast.value).expect("template has been validated");
+    let mut value = String::new();
+    for part in parts {
+        match part {
+            TemplatePartAst::Literal(literal) => value.push_str(&literal),
+            TemplatePartAst::Variable(variable) => {
+                value.push_str(variables.get(&variable).context(UndefinedVariableSnafu {
+                    directive: safe_unicode_display(text),
+                    variable,
+                })?)
+            }
+        }
+    }
+    ast.operator = OperatorAst::EqualSign;
+    ast.value = value;
+    Ok(())
+}
+
+/// Settings affecting how directives are composed.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Variables available to interpolating string directives
+    pub variables: HashMap<String, String>,
+    /// Whether deleting a path that does not exist is ignored rather than an error
+    pub ignore_missing: bool,
+}
+
+/// The directive as it was written, for reporting errors.
+struct Origin {
+    directive: String,
+    input: Input,
+}
+
+/// An open group of directives sharing a common path prefix.
+///
+/// A group path with brace lists or ranges results in several prefixes.
+struct Group {
+    prefixes: Vec<Vec<SegmentAst>>,
+    origin: Origin,
+}
+
+/// The leaves of a base document that directives are applied on top of.
+#[derive(Default)]
+struct Base {
+    directives: Vec<Directive>,
+    kinds: HashMap<Rc<Path>, NodeKind>,
+    /// Paths assigned by directives, replacing the base at and below them
+    replaced: HashSet<Rc<Path>>,
+    /// Paths that directives assign below
+    traversed: HashSet<Rc<Path>>,
+}
+
+impl Base {
+    fn new(directives: Vec<Directive>) -> Self {
+        let mut kinds = HashMap::new();
+        for directive in &directives {
+            let kind = match directive.value.as_str() {
+                "[]" => NodeKind::Array,
+                "{}" => NodeKind::Object,
+                _ => NodeKind::Value,
+            };
+            kinds.insert(directive.path.clone(), kind);
+            for (prefix, segment) in directive.path.iter() {
+                kinds.insert(prefix, segment.kind());
+            }
+        }
+        Base {
+            directives,
+            kinds,
+            ..Base::default()
+        }
+    }
+
+    /// Replaces the subtree at the path of a directive.
+    ///
+    /// Directives may replace any part of the base, but they must not descend into it as
+    /// anything other than what it is.
+    fn replace(&mut self, path: &Rc<Path>) -> Result<(), PathError> {
+        for (prefix, segment) in path.iter() {
+            match self.kinds.get(&prefix) {
+                Some(&kind) if kind != segment.kind() => Err(PathError {
+                    path: prefix.clone(),
+                    variant: PathErrorVariant::StructuralConflict {
+                        kind1: kind,
+                        kind2: segment.kind(),
+                    },
+                })?,
+                _ => {}
+            }
+            self.traversed.insert(prefix);
+        }
+        self.replaced.insert(path.clone());
+        Ok(())
+    }
+
+    /// Returns the leaves of the base that have not been replaced.
+    ///
+    /// Empty objects and arrays are dropped in favor of directives assigning members to them.
+    fn into_remaining(self) -> Vec<Directive> {
+        let Base {
+            directives,
+            replaced,
+            traversed,
+            ..
+        } = self;
+        directives
+            .into_iter()
+            .filter(|directive| {
+                !replaced.contains(&directive.path)
+                    && !directive
+                        .path
+                        .iter()
+                        .any(|(prefix, _)| replaced.contains(&prefix))
+                    && !traversed.contains(&directive.path)
+            })
+            .collect()
+    }
+}
+
+struct Composer<'a> {
+    options: &'a Options,
+    /// The base document, as edited by deletions, moves and copies
+    tree: Option<Node>,
+    base: Base,
+    assigned: bool,
+    directives: Vec<Directive>,
+    origins: Vec<Rc<Origin>>,
+    indices: IndexAllocator,
+    groups: Vec<Group>,
+    stdin: Option<Vec<u8>>,
+}
+
+impl<'a> Composer<'a> {
+    fn new(options: &'a Options, tree: Option<Node>) -> Self {
+        let mut composer = Composer {
+            options,
+            tree,
+            base: Base::default(),
+            assigned: false,
+            directives: vec![],
+            origins: vec![],
+            indices: IndexAllocator::default(),
+            groups: vec![],
+            stdin: None,
+        };
+        composer.rebase();
+        composer
+    }
+
+    /// Brings the base and the relative indices up to date with the edited base document.
+    fn rebase(&mut self) {
+        let directives = self
+            .tree
+            .as_ref()
+            .map(|tree| tree.to_directives())
+            .unwrap_or_default();
+        self.indices = IndexAllocator::default();
+        for directive in &directives {
+            self.indices.record(&directive.path);
+        }
+        self.base = Base::new(directives);
+    }
+
+    fn take(&mut self, path: &Rc<Path>) -> Option<Node> {
+        if path.is_empty() {
+            self.tree.take()
+        } else {
+            self.tree.as_mut()?.remove(path)
+        }
+    }
+
+    fn put(&mut self, path: &Rc<Path>, node: Node) -> Result<(), PathError> {
+        let Some((first, _)) = path.split_first() else {
+            self.tree = Some(node);
+            return Ok(());
+        };
+        let tree = self.tree.get_or_insert_with(|| match first {
+            Segment::Index(_) => Node::Array(Default::default()),
+            Segment::Key(_) => Node::Object(Default::default()),
+        });
+        tree.replace(path, node)
+    }
+
+    fn check_edit_order(&self, text: &str) -> BuildResult<()> {
+        ensure!(
+            !self.assigned,
+            EditAfterAssignmentSnafu {
+                directive: safe_unicode_display(text),
+            }
+        );
+        Ok(())
+    }
+
+    /// Deletes the subtrees at the paths of a deletion.
+    ///
+    /// Paths are deleted in descending order, so that removing an array element doesn't shift
+    /// the elements that remain to be deleted.
+    fn delete(&mut self, path: &[SegmentAst], text: &str) -> BuildResult<()> {
+        self.check_edit_order(text)?;
+        let mut paths: Vec<Rc<Path>> = self
+            .resolve(path, text)?
+            .into_iter()
+            .map(|path| path.into_iter().map(Segment::from).collect())
+            .collect();
+        paths.sort();
+        for path in paths.iter().rev() {
+            if self.take(path).is_none() && !self.options.ignore_missing {
+                Err(PathError {
+                    path: path.clone(),
+                    variant: PathErrorVariant::NoSuchPath,
+                })
+                .context(PathSnafu {
+                    directive: safe_unicode_display(text),
+                })?;
+            }
+        }
+        self.rebase();
+        Ok(())
+    }
+
+    /// Copies or moves the subtree at the source path of a directive to its paths.
+    ///
+    /// The source path is not affected by groups, and a moved subtree is removed before the
+    /// paths it is moved to are resolved.
+    fn copy(&mut self, ast: &DirectiveAst, text: &str) -> BuildResult<()> {
+        self.check_edit_order(text)?;
+        let (source, _, _) = parse_complete_path(1, &ast.value).expect("path has been validated");
+        let [mut source] =
+            expand_path(&source)
+                .try_into()
+                .map_err(|_| BuildError::AmbiguousSource {
+                    directive: safe_unicode_display(text),
+                })?;
+        self.indices.resolve(&mut source).context(ResolveSnafu {
+            directive: safe_unicode_display(text),
+        })?;
+        let source: Rc<Path> = source.into_iter().map(Segment::from).collect();
+        let node = if ast.operator == OperatorAst::Move {
+            self.take(&source)
+        } else {
+            self.tree
+                .as_ref()
+                .and_then(|tree| tree.get(&source))
+                .cloned()
+        };
+        let node = node
+            .ok_or_else(|| PathError {
+                path: source.clone(),
+                variant: PathErrorVariant::NoSuchPath,
+            })
+            .context(PathSnafu {
+                directive: safe_unicode_display(text),
+            })?;
+        self.rebase();
+        for path in self.resolve(&ast.path, text)? {
+            let path = path.into_iter().map(Segment::from).collect();
+            self.put(&path, node.clone()).context(PathSnafu {
+                directive: safe_unicode_display(text),
+            })?;
+        }
+        self.rebase();
+        Ok(())
+    }
+
+    fn add(&mut self, input: &Input) -> BuildResult<()> {
+        let bytes = &input.bytes;
+        let text = str::from_utf8(bytes).context(EncodingSnafu {
+            directive: safe_bytes_display(bytes),
         })?;
-        let (ast, _, _) = parse_directive(1, text).context(SyntaxSnafu {
+        let (statement, _, _) = parse_statement(1, text).context(SyntaxSnafu {
             directive: safe_unicode_display(text),
         })?;
-        directives.push(ast.into());
+        let origin = Origin {
+            directive: safe_unicode_display(text),
+            input: input.clone(),
+        };
+        let mut ast = match statement {
+            StatementAst::Directive(ast) => ast,
+            StatementAst::OpenGroup(path) => {
+                let prefixes = self.resolve(&path, text)?;
+                self.groups.push(Group { prefixes, origin });
+                return Ok(());
+            }
+            StatementAst::CloseGroup => {
+                self.groups.pop().context(UnbalancedGroupSnafu {
+                    directive: safe_unicode_display(text),
+                })?;
+                return Ok(());
+            }
+            StatementAst::Delete(path) => return self.delete(&path, text),
+        };
+        match ast.source {
+            SourceAst::Inline => {}
+            SourceAst::File => load_file(&mut ast, &mut self.stdin, text)?,
+            SourceAst::Variable => load_variable(&mut ast, text)?,
+        }
+        if ast.operator == OperatorAst::Template {
+            interpolate(&mut ast, &self.options.variables, text)?;
+        }
+        if ast.operator.is_edit() {
+            return self.copy(&ast, text);
+        }
+        self.assigned = true;
+        let origin = Rc::new(origin);
+        for path in self.resolve(&ast.path, text)? {
+            let root = path.iter().cloned().map(Segment::from).collect();
+            self.base.replace(&root).context(PathSnafu {
+                directive: safe_unicode_display(text),
+            })?;
+            for directive in Directive::from_ast(DirectiveAst {
+                path,
+                ..ast.clone()
+            }) {
+                self.indices.record(&directive.path);
+                self.directives.push(directive);
+                self.origins.push(origin.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Puts the prefixes of the current group in front of a path and expands it into concrete
+    /// paths.
+    ///
+    /// All concrete paths have their relative indices resolved before any of them is recorded,
+    /// so that a `+` segment refers to the same index in each of them.
+    fn resolve(&self, path: &[SegmentAst], text: &str) -> BuildResult<Vec<Vec<SegmentAst>>> {
+        let prefixes = match self.groups.last() {
+            Some(group) => group.prefixes.clone(),
+            None => vec![vec![]],
+        };
+        if prefixes.len().saturating_mul(count_paths(path)) > MAX_PATHS {
+            Err(SyntaxError::TooManyPaths { pos: 1 }).context(SyntaxSnafu {
+                directive: safe_unicode_display(text),
+            })?;
+        }
+        let mut paths = vec![];
+        for prefix in prefixes {
+            for expanded in expand_path(path) {
+                let mut path = prefix.clone();
+                path.extend(expanded);
+                self.indices.resolve(&mut path).context(ResolveSnafu {
+                    directive: safe_unicode_display(text),
+                })?;
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+}
+
+/// Prefixes an error with the location of the directive that caused it, if it is known.
+fn locate(error: BuildError, input: &Input) -> BuildError {
+    let Some(location) = &input.location else {
+        return error;
+    };
+    let location = match &error {
+        BuildError::Syntax { source, .. } => {
+            let text = String::from_utf8_lossy(&input.bytes);
+            let pos = source.pos().unwrap_or(text.chars().count() + 1);
+            location.advance(&text, pos)
+        }
+        _ => location.clone(),
+    };
+    BuildError::Located {
+        location,
+        source: Box::new(error),
+    }
+}
+
+/// Returns whether an input is a directive that reads its value from stdin, i.e. from the file
+/// `-`.
+///
+/// Inputs that are not valid directives do not read anything.
+pub fn reads_stdin(input: &Input) -> bool {
+    let Ok(text) = str::from_utf8(&input.bytes) else {
+        return false;
+    };
+    matches!(
+        parse_statement(1, text),
+        Ok((StatementAst::Directive(DirectiveAst { source: SourceAst::File, value, .. }), _, _))
+        if value == "-"
+    )
+}
+
+pub fn compose<I: Into<Input>>(inputs: impl Iterator<Item = I>) -> BuildResult<Option<Node>> {
+    compose_with(inputs, &Options::default())
+}
+
+pub fn compose_with<I: Into<Input>>(
+    inputs: impl Iterator<Item = I>,
+    options: &Options,
+) -> BuildResult<Option<Node>> {
+    run(Composer::new(options, None), inputs)
+}
+
+/// Applies directives on top of an existing tree.
+///
+/// Each directive replaces the subtree at its path.
+/// The tree is empty if it is deleted as a whole and nothing is assigned.
+pub fn apply<I: Into<Input>>(
+    base: &Node,
+    inputs: impl Iterator<Item = I>,
+    options: &Options,
+) -> BuildResult<Option<Node>> {
+    run(Composer::new(options, Some(base.clone())), inputs)
+}
+
+fn run<I: Into<Input>>(
+    mut composer: Composer,
+    inputs: impl Iterator<Item = I>,
+) -> BuildResult<Option<Node>> {
+    for input in inputs {
+        let input = input.into();
+        composer
+            .add(&input)
+            .map_err(|error| locate(error, &input))?;
+    }
+    if let Some(Group { origin, .. }) = composer.groups.pop() {
+        Err(locate(
+            BuildError::UnclosedGroup {
+                directive: origin.directive,
+            },
+            &origin.input,
+        ))?;
     }
 
-    validate(directives.as_slice()).context(PathSnafu)?;
+    let mut directives = composer.base.into_remaining();
+    let base_len = directives.len();
+    directives.extend(composer.directives);
 
-    Ok(build_tree(directives.into_iter()))
+    if let Err(error) = validate(directives.as_slice()) {
+        let Some(i) = error.directive.checked_sub(base_len) else {
+            Err(BuildError::Base {
+                source: error.source,
+            })?
+        };
+        let origin = &composer.origins[i];
+        Err(locate(
+            BuildError::Path {
+                source: error.source,
+                directive: origin.directive.clone(),
+            },
+            &origin.input,
+        ))?;
+    }
+
+    let mut tree = build_tree(directives.into_iter());
+    if let (Some(tree), Some(base)) = (&mut tree, &composer.tree) {
+        tree.order_like(base);
+    }
+    Ok(tree)
 }
 
 #[cfg(test)]
@@ -96,6 +721,7 @@ mod tests {
     use crate::directive::Path;
     use crate::parser::SyntaxError::*;
     use crate::parser::parse_path;
+    use crate::testing::TempDir;
     use crate::validator::NodeKind;
     use crate::validator::PathErrorVariant::*;
     use assert_matches::assert_matches;
@@ -128,6 +754,7 @@ mod tests {
                         path,
                         variant: $expected,
                     },
+                    ..
                 })
                 if path == new_path($path)
             );
@@ -140,7 +767,7 @@ mod tests {
     }
 
     fn check(directives: &[&str]) -> BuildResult<Option<String>> {
-        let directives = directives.into_iter().map(|s| s.bytes().collect());
+        let directives = directives.iter().map(|s| s.bytes().collect::<Vec<u8>>());
         compose(directives).map(|tree| tree.map(|node| node.to_string()))
     }
 
@@ -160,6 +787,80 @@ mod tests {
                 expect_syntax_error!(["00=x"], UnexpectedChar { pos: 2, ch: '0' });
                 expect_syntax_error!(["01=x"], UnexpectedChar { pos: 2, ch: '1' });
             }
+
+            #[test]
+            fn accept_relative_index_segments() {
+                expect_json!(["+:42"], "[42]");
+                expect_json!(["+.foo:42", "+0.bar:43"], r#"[{"bar":43,"foo":42}]"#);
+            }
+
+            #[test]
+            fn reject_relative_index_segments_with_trailing_digits() {
+                expect_syntax_error!(["+1=x"], UnexpectedChar { pos: 2, ch: '1' });
+                expect_syntax_error!(["+01=x"], UnexpectedChar { pos: 3, ch: '1' });
+            }
+        }
+
+        mod expansion {
+            use super::*;
+
+            #[test]
+            fn accept_brace_list_segment() {
+                expect_json!(["{a,b}:1"], r#"{"a":1,"b":1}"#);
+                expect_json!(["{0,1}:1"], "[1,1]");
+                expect_json!([r#"{"a b",c}:1"#], r#"{"a b":1,"c":1}"#);
+                expect_json!(["{a}:1"], r#"{"a":1}"#);
+            }
+
+            #[test]
+            fn accept_range_segment() {
+                expect_json!(["0..2:1"], "[1,1,1]");
+                expect_json!(["0..0:1"], "[1]");
+                expect_json!(["{0..1,2}:1"], "[1,1,1]");
+            }
+
+            #[test]
+            fn reject_too_many_paths() {
+                expect_syntax_error!(["a.0..4294967295=x"], TooManyPaths { pos: 1 });
+                expect_syntax_error!(["{0..999}.0..999:1"], TooManyPaths { pos: 1 });
+                expect_syntax_error!(["0..999.{", "0..999:1", "}"], TooManyPaths { pos: 1 });
+            }
+
+            #[test]
+            fn reject_empty_range() {
+                expect_syntax_error!(
+                    ["a.2..1=x"],
+                    EmptyRange {
+                        pos: 3,
+                        start: 2,
+                        end: 1
+                    }
+                );
+            }
+
+            #[test]
+            fn reject_malformed_ranges() {
+                expect_syntax_error!(["0..=x"], UnexpectedChar { pos: 4, ch: '=' });
+                expect_syntax_error!(["0..01=x"], UnexpectedChar { pos: 5, ch: '1' });
+                expect_syntax_error!(["0...1=x"], UnexpectedChar { pos: 4, ch: '.' });
+                expect_syntax_error!(["a..1=x"], UnexpectedChar { pos: 3, ch: '.' });
+            }
+
+            #[test]
+            fn reject_malformed_brace_lists() {
+                expect_syntax_error!(["{}=x"], UnexpectedChar { pos: 2, ch: '}' });
+                expect_syntax_error!(["{a,}=x"], UnexpectedChar { pos: 4, ch: '}' });
+                expect_syntax_error!(["{a b}=x"], UnexpectedChar { pos: 3, ch: ' ' });
+                expect_syntax_error!(["{a,b=x"], UnexpectedChar { pos: 5, ch: '=' });
+                expect_syntax_error!(["{a"], UnexpectedEndOfString);
+            }
+
+            #[test]
+            fn reject_nested_and_relative_alternatives() {
+                expect_syntax_error!(["{a,{b}}=x"], UnexpectedChar { pos: 4, ch: '{' });
+                expect_syntax_error!(["{+}=x"], UnexpectedChar { pos: 2, ch: '+' });
+                expect_syntax_error!(["{0,+0}=x"], UnexpectedChar { pos: 4, ch: '+' });
+            }
         }
 
         mod bare_key {
@@ -471,8 +1172,19 @@ mod tests {
             }
 
             #[test]
-            fn reject_non_empty_object() {
-                expect_syntax_error!([r#".:{"foo":42}"#], UnexpectedChar { pos: 4, ch: '"' });
+            fn accept_and_preserve_non_empty_object() {
+                expect_json!([r#".:{"foo":42}"#], r#"{"foo":42}"#);
+                expect_json!(
+                    [r#".:{"b":"\u2600","a":{"c":1.00,"d":[]}}"#],
+                    r#"{"a":{"c":1.00,"d":[]},"b":"\u2600"}"#
+                );
+            }
+
+            #[test]
+            fn reject_invalid_object() {
+                expect_syntax_error!([r#".:{"foo":}"#], InvalidJsonValue { pos: 3, .. });
+                expect_syntax_error!([r#".:{"foo":1"#], InvalidJsonValue { pos: 3, .. });
+                expect_syntax_error!([r#".:{"foo":1}}"#], UnexpectedChar { pos: 12, ch: '}' });
             }
 
             #[test]
@@ -481,100 +1193,463 @@ mod tests {
             }
 
             #[test]
-            fn reject_non_empty_array() {
-                expect_syntax_error!([".:[42]"], UnexpectedChar { pos: 4, ch: '4' });
+            fn accept_and_preserve_non_empty_array() {
+                expect_json!([".:[42]"], "[42]");
+                expect_json!([r#".:[1e400,"\"",[{}],null]"#], r#"[1e400,"\"",[{}],null]"#);
             }
-        }
-
-        mod directive {
-            use super::*;
 
             #[test]
-            fn reject_incomplete_directive() {
-                expect_syntax_error!([""], UnexpectedEndOfString);
-                expect_syntax_error!(["foo"], UnexpectedEndOfString);
+            fn reject_invalid_array() {
+                expect_syntax_error!([".:[42,]"], InvalidJsonValue { pos: 3, .. });
+                expect_syntax_error!([".:[42"], InvalidJsonValue { pos: 3, .. });
             }
         }
-    }
-
-    mod semantics {
-        use super::*;
 
-        mod colliding_assignments {
+        mod file_sources {
             use super::*;
 
             #[test]
-            fn reject_conflicting_root_assignments() {
-                expect_path_error!([".:42", ".:43"], ".", ConflictingDirectives);
+            fn accept_string_from_file() {
+                let dir = TempDir::new("file-sources");
+                let file = dir.file("string", b"line \"1\"\nline 2\n");
+                expect_json!(
+                    [format!("a@={file}").as_str()],
+                    r#"{"a":"line \"1\"\nline 2\n"}"#
+                );
             }
 
             #[test]
-            fn reject_duplicate_object_keys() {
-                expect_path_error!(["a:42", "a:42"], "a", ConflictingDirectives);
-                expect_path_error!(["a:42", r#""a":42"#], "a", ConflictingDirectives);
-                expect_path_error!([r#""a":42"#, r#""a":42"#], "a", ConflictingDirectives);
+            fn accept_json_from_file() {
+                let dir = TempDir::new("file-sources");
+                let file = dir.file("json", b"{\"b\": [1.00, \"\\u2600\"]}\n");
+                expect_json!(
+                    [format!("a@:{file}").as_str(), "a.c:true"],
+                    r#"{"a":{"b":[1.00,"\u2600"],"c":true}}"#
+                );
             }
 
             #[test]
-            fn reject_ambiguous_escape_encodings() {
-                expect_path_error!(
-                    ["a:42", r#""\u0061":42"#],
-                    ".",
-                    InconsistentKeyEncodings { .. } // FIXME: check the encodings too
-                );
-                expect_path_error!(
-                    [r#""\u006a":42"#, r#""\u006A":42"#],
-                    ".",
-                    InconsistentKeyEncodings { .. } // FIXME: check the encodings too
+            fn reject_missing_file() {
+                assert_matches!(
+                    check(&["a@=/nonexistent/mkjson"]),
+                    Err(BuildError::ReadFile { directive, file, .. })
+                    if directive == "a@=/nonexistent/mkjson" && file == "/nonexistent/mkjson"
                 );
             }
 
             #[test]
-            fn accept_nfc_nfd_nfkc_nfkd_encodings_distinct_keys_per_rfc_8259() {
-                // LATIN SMALL LETTER A WITH DIAERESIS
-                expect_json!(
-                    [
-                        "a\u{308}:42", // NFD and NFKD
-                        "\u{e4}:42",   // NFC and NFKC
-                    ],
-                    "{\"a\u{308}\":42,\"\u{e4}\":42}"
+            fn reject_invalid_json_in_file() {
+                let dir = TempDir::new("file-sources");
+                let file = dir.file("invalid-json", b"[1,]");
+                assert_matches!(
+                    check(&[format!("a@:{file}").as_str()]),
+                    Err(BuildError::FileSyntax {
+                        source: InvalidJsonValue { pos: 1, .. },
+                        file: error_file,
+                        ..
+                    })
+                    if error_file == file
                 );
+            }
 
-                // LATIN SMALL LIGATURE FI + COMBINING ACUTE ACCENT
-                expect_json!(
-                    [
-                        "fi\u{301}:42",       // NFKC and NFKD
-                        "\u{fb01}\u{301}:42", // NFC and NFD
-                    ],
-                    "{\"fi\u{301}\":42,\"\u{fb01}\u{301}\":42}"
+            #[test]
+            fn reject_invalid_utf8_in_file() {
+                let dir = TempDir::new("file-sources");
+                let file = dir.file("invalid-utf8", b"\xff");
+                assert_matches!(
+                    check(&[format!("a@={file}").as_str()]),
+                    Err(BuildError::FileEncoding { file: error_file, .. })
+                    if error_file == file
                 );
             }
 
             #[test]
-            fn reject_duplicate_array_indices() {
-                expect_path_error!(["0:42", "0:43"], "0", ConflictingDirectives);
+            fn reject_file_source_without_operator() {
+                expect_syntax_error!(["a@x"], UnexpectedChar { pos: 3, ch: 'x' });
+                expect_syntax_error!(["a@"], UnexpectedEndOfString);
             }
         }
 
-        mod type_conflicts {
+        mod variable_sources {
             use super::*;
 
+            // Cargo sets these variables when running tests.
             #[test]
-            fn reject_inconsistent_object_and_array_structures() {
-                expect_path_error!(
-                    ["foo.0=x", "foo.bar=y"],
-                    "foo",
-                    StructuralConflict {
-                        kind1: NodeKind::Array,
-                        kind2: NodeKind::Object,
-                    }
+            fn accept_string_from_variable() {
+                expect_json!(["a$=CARGO_PKG_NAME"], r#"{"a":"mkjson"}"#);
+            }
+
+            #[test]
+            fn accept_json_from_variable() {
+                expect_json!(["a$:CARGO_PKG_VERSION_MAJOR"], r#"{"a":0}"#);
+            }
+
+            #[test]
+            fn reject_invalid_json_in_variable() {
+                assert_matches!(
+                    check(&["a$:CARGO_PKG_NAME"]),
+                    Err(BuildError::VariableSyntax {
+                        source: InvalidJsonValue { pos: 1, .. },
+                        variable,
+                        ..
+                    })
+                    if variable == "CARGO_PKG_NAME"
                 );
-                expect_path_error!(
-                    ["foo.bar=x", "foo.0=y"],
-                    "foo",
-                    StructuralConflict {
-                        kind1: NodeKind::Object,
-                        kind2: NodeKind::Array,
+            }
+
+            #[test]
+            fn reject_unset_variable() {
+                assert_matches!(
+                    check(&["a$=MKJSON_TEST_UNSET_VARIABLE"]),
+                    Err(BuildError::MissingVariable { directive, variable })
+                    if directive == "a$=MKJSON_TEST_UNSET_VARIABLE"
+                        && variable == "MKJSON_TEST_UNSET_VARIABLE"
+                );
+                assert_matches!(check(&["a$=A=B"]), Err(BuildError::MissingVariable { .. }));
+            }
+
+            #[test]
+            fn reject_empty_variable_name() {
+                expect_syntax_error!(["a$="], UnexpectedEndOfString);
+                expect_syntax_error!(["a$#int="], UnexpectedEndOfString);
+            }
+        }
+
+        mod typed_values {
+            use super::*;
+
+            #[test]
+            fn accept_and_preserve_numbers() {
+                expect_json!(["port#=8080"], r#"{"port":8080}"#);
+                expect_json!([".#=-0"], "-0");
+                expect_json!([".#=1.00"], "1.00");
+                expect_json!([".#=6.02E+23"], "6.02E+23");
+                expect_json!([".#=1e400"], "1e400");
+            }
+
+            #[test]
+            fn reject_non_numbers() {
+                assert_matches!(
+                    check(&["port#=abc"]),
+                    Err(BuildError::Syntax {
+                        source: InvalidNumber { pos: 7, value },
+                        ..
+                    })
+                    if value == "abc"
+                );
+                expect_syntax_error!([".#="], InvalidNumber { pos: 4, .. });
+                expect_syntax_error!([".#= 1"], InvalidNumber { .. });
+                expect_syntax_error!([".#=01"], InvalidNumber { .. });
+                expect_syntax_error!([".#=1."], InvalidNumber { .. });
+                expect_syntax_error!([".#=.1"], InvalidNumber { .. });
+                expect_syntax_error!([".#=1e"], InvalidNumber { .. });
+                expect_syntax_error!([".#=+1"], InvalidNumber { .. });
+                expect_syntax_error!([".#=NaN"], InvalidNumber { .. });
+                expect_syntax_error!([".#=true"], InvalidNumber { .. });
+            }
+
+            #[test]
+            fn accept_and_preserve_decimal_integers() {
+                expect_json!([".#int=0"], "0");
+                expect_json!([".#int=-0"], "-0");
+                expect_json!([".#int=42"], "42");
+                expect_json!(
+                    [".#int=340282366920938463463374607431768211456"],
+                    "340282366920938463463374607431768211456"
+                );
+            }
+
+            #[test]
+            fn normalize_hexadecimal_and_octal_integers() {
+                expect_json!([".#int=0xff"], "255");
+                expect_json!([".#int=0xFF"], "255");
+                expect_json!([".#int=-0x10"], "-16");
+                expect_json!([".#int=0o17"], "15");
+                expect_json!([".#int=-0o0"], "0");
+            }
+
+            #[test]
+            fn reject_non_integers() {
+                assert_matches!(
+                    check(&["n#int=abc"]),
+                    Err(BuildError::Syntax {
+                        source: InvalidInteger { pos: 7, value },
+                        ..
+                    })
+                    if value == "abc"
+                );
+                expect_syntax_error!([".#int="], InvalidInteger { .. });
+                expect_syntax_error!([".#int=1.0"], InvalidInteger { .. });
+                expect_syntax_error!([".#int=1e3"], InvalidInteger { .. });
+                expect_syntax_error!([".#int=017"], InvalidInteger { .. });
+                expect_syntax_error!([".#int=0x"], InvalidInteger { .. });
+                expect_syntax_error!([".#int=0xg"], InvalidInteger { .. });
+                expect_syntax_error!([".#int=0o8"], InvalidInteger { .. });
+                expect_syntax_error!([".#int=0X1"], InvalidInteger { .. });
+                expect_syntax_error!(
+                    [".#int=0x100000000000000000000000000000000"],
+                    InvalidInteger { .. }
+                );
+            }
+
+            #[test]
+            fn accept_booleans() {
+                expect_json!([".#bool=true"], "true");
+                expect_json!([".#bool=false"], "false");
+            }
+
+            #[test]
+            fn reject_non_booleans() {
+                expect_syntax_error!([".#bool="], InvalidBoolean { pos: 8, .. });
+                expect_syntax_error!([".#bool=True"], InvalidBoolean { .. });
+                expect_syntax_error!([".#bool=1"], InvalidBoolean { .. });
+            }
+
+            #[test]
+            fn trim_typed_values_from_files() {
+                let dir = TempDir::new("typed-values");
+                let number = dir.file("number", "123\n");
+                let boolean = dir.file("boolean", "true \r\n");
+                expect_json!(
+                    [
+                        format!("n@#={number}").as_str(),
+                        format!("b@#bool={boolean}").as_str()
+                    ],
+                    r#"{"b":true,"n":123}"#
+                );
+                let lines = dir.file("lines", "1\n2\n");
+                assert_matches!(
+                    check(&[format!("n@#={lines}").as_str()]),
+                    Err(BuildError::FileSyntax {
+                        source: InvalidNumber { pos: 1, value },
+                        ..
+                    })
+                    if value == "1\n2"
+                );
+            }
+
+            #[test]
+            fn validate_typed_values_from_variables() {
+                expect_json!(["v$#int=CARGO_PKG_VERSION_MAJOR"], r#"{"v":0}"#);
+                assert_matches!(
+                    check(&["v$#bool=CARGO_PKG_NAME"]),
+                    Err(BuildError::VariableSyntax {
+                        source: InvalidBoolean { pos: 1, .. },
+                        ..
+                    })
+                );
+            }
+        }
+
+        mod binary_values {
+            use super::*;
+
+            #[test]
+            fn encode_hex_as_base64() {
+                expect_json!([".#base64="], r#""""#);
+                expect_json!([".#base64=66"], r#""Zg==""#);
+                expect_json!([".#base64=666f"], r#""Zm8=""#);
+                expect_json!([".#base64=666f6f"], r#""Zm9v""#);
+                expect_json!([".#base64=666F6F626172"], r#""Zm9vYmFy""#);
+                expect_json!([".#base64=fbff"], r#""+/8=""#);
+            }
+
+            #[test]
+            fn encode_hex_as_unpadded_base64url() {
+                expect_json!([".#base64url=66"], r#""Zg""#);
+                expect_json!([".#base64url=fbff"], r#""-_8""#);
+                expect_json!([".#base64url=666f6f"], r#""Zm9v""#);
+            }
+
+            #[test]
+            fn encode_file_as_base64() {
+                let dir = TempDir::new("binary");
+                let file = dir.file("binary", b"\xfb\xff\x00");
+                expect_json!(
+                    [
+                        format!("a@#base64={file}").as_str(),
+                        format!("b@#base64url={file}").as_str()
+                    ],
+                    r#"{"a":"+/8A","b":"-_8A"}"#
+                );
+            }
+
+            #[test]
+            fn reject_invalid_hex() {
+                expect_syntax_error!([".#base64=6"], UnexpectedEndOfString);
+                expect_syntax_error!([".#base64=0x66"], UnexpectedChar { pos: 11, ch: 'x' });
+                expect_syntax_error!([".#base64=66 "], UnexpectedChar { pos: 12, ch: ' ' });
+            }
+
+            #[test]
+            fn reject_unknown_type() {
+                assert_matches!(
+                    check(&[".#base32=66"]),
+                    Err(BuildError::Syntax {
+                        source: UnknownType { pos: 3, name },
+                        ..
+                    })
+                    if name == "base32"
+                );
+                expect_syntax_error!([".#base64:66"], UnexpectedChar { pos: 9, ch: ':' });
+            }
+        }
+
+        mod interpolated_values {
+            use super::*;
+
+            fn check_with(directives: &[&str]) -> BuildResult<Option<String>> {
+                let options = Options {
+                    variables: HashMap::from([
+                        ("host".to_string(), "example.com".to_string()),
+                        ("v_2".to_string(), "\"2\"".to_string()),
+                        ("empty".to_string(), String::new()),
+                    ]),
+                    ..Options::default()
+                };
+                let directives = directives.iter().map(|s| s.bytes().collect::<Vec<u8>>());
+                compose_with(directives, &options).map(|tree| tree.map(|node| node.to_string()))
+            }
+
+            #[test]
+            fn substitute_variables() {
+                assert_eq!(
+                    check_with(&["url%=https://${host}/api/${v_2}"]).unwrap(),
+                    Some(r#"{"url":"https://example.com/api/\"2\""}"#.to_string())
+                );
+                assert_eq!(
+                    check_with(&[".%=${empty}${host}${host}"]).unwrap(),
+                    Some(r#""example.comexample.com""#.to_string())
+                );
+                assert_eq!(check_with(&[".%="]).unwrap(), Some(r#""""#.to_string()));
+            }
+
+            #[test]
+            fn keep_escaped_and_unrelated_dollar_signs() {
+                assert_eq!(
+                    check_with(&[".%=$${host} $host $ $$ ${host}$"]).unwrap(),
+                    Some(r#""${host} $host $ $$ example.com$""#.to_string())
+                );
+            }
+
+            #[test]
+            fn reject_undefined_variables() {
+                assert_matches!(
+                    check_with(&[".%=${hots}"]),
+                    Err(BuildError::UndefinedVariable { variable, .. }) if variable == "hots"
+                );
+                assert_matches!(
+                    check(&[".%=${host}"]),
+                    Err(BuildError::UndefinedVariable { variable, .. }) if variable == "host"
+                );
+            }
+
+            #[test]
+            fn reject_malformed_references() {
+                expect_syntax_error!([".%=${}"], UnexpectedChar { pos: 6, ch: '}' });
+                expect_syntax_error!([".%=a${b-c}"], UnexpectedChar { pos: 8, ch: '-' });
+                expect_syntax_error!([".%=${host"], UnexpectedEndOfString);
+                expect_syntax_error!([".%x"], UnexpectedChar { pos: 2, ch: '%' });
+            }
+
+            #[test]
+            fn interpolate_variable_sources() {
+                assert_eq!(
+                    check_with(&["a$%=CARGO_PKG_NAME"]).unwrap(),
+                    Some(r#"{"a":"mkjson"}"#.to_string())
+                );
+            }
+        }
+
+        mod directive {
+            use super::*;
+
+            #[test]
+            fn reject_incomplete_directive() {
+                expect_syntax_error!([""], UnexpectedEndOfString);
+                expect_syntax_error!(["foo"], UnexpectedEndOfString);
+            }
+        }
+    }
+
+    mod semantics {
+        use super::*;
+
+        mod colliding_assignments {
+            use super::*;
+
+            #[test]
+            fn reject_conflicting_root_assignments() {
+                expect_path_error!([".:42", ".:43"], ".", ConflictingDirectives);
+            }
+
+            #[test]
+            fn reject_duplicate_object_keys() {
+                expect_path_error!(["a:42", "a:42"], "a", ConflictingDirectives);
+                expect_path_error!(["a:42", r#""a":42"#], "a", ConflictingDirectives);
+                expect_path_error!([r#""a":42"#, r#""a":42"#], "a", ConflictingDirectives);
+            }
+
+            #[test]
+            fn reject_ambiguous_escape_encodings() {
+                expect_path_error!(
+                    ["a:42", r#""\u0061":42"#],
+                    ".",
+                    InconsistentKeyEncodings { .. } // FIXME: check the encodings too
+                );
+                expect_path_error!(
+                    [r#""\u006a":42"#, r#""\u006A":42"#],
+                    ".",
+                    InconsistentKeyEncodings { .. } // FIXME: check the encodings too
+                );
+            }
+
+            #[test]
+            fn accept_nfc_nfd_nfkc_nfkd_encodings_distinct_keys_per_rfc_8259() {
+                // LATIN SMALL LETTER A WITH DIAERESIS
+                expect_json!(
+                    [
+                        "a\u{308}:42", // NFD and NFKD
+                        "\u{e4}:42",   // NFC and NFKC
+                    ],
+                    "{\"a\u{308}\":42,\"\u{e4}\":42}"
+                );
+
+                // LATIN SMALL LIGATURE FI + COMBINING ACUTE ACCENT
+                expect_json!(
+                    [
+                        "fi\u{301}:42",       // NFKC and NFKD
+                        "\u{fb01}\u{301}:42", // NFC and NFD
+                    ],
+                    "{\"fi\u{301}\":42,\"\u{fb01}\u{301}\":42}"
+                );
+            }
+
+            #[test]
+            fn reject_duplicate_array_indices() {
+                expect_path_error!(["0:42", "0:43"], "0", ConflictingDirectives);
+            }
+        }
+
+        mod type_conflicts {
+            use super::*;
+
+            #[test]
+            fn reject_inconsistent_object_and_array_structures() {
+                expect_path_error!(
+                    ["foo.0=x", "foo.bar=y"],
+                    "foo",
+                    StructuralConflict {
+                        kind1: NodeKind::Array,
+                        kind2: NodeKind::Object,
+                    }
+                );
+                expect_path_error!(
+                    ["foo.bar=x", "foo.0=y"],
+                    "foo",
+                    StructuralConflict {
+                        kind1: NodeKind::Object,
+                        kind2: NodeKind::Array,
                     }
                 );
                 expect_path_error!(
@@ -669,6 +1744,621 @@ mod tests {
             }
         }
 
+        mod relative_indices {
+            use super::*;
+
+            #[test]
+            fn append_to_the_next_free_index() {
+                expect_json!(["+=x", "+=y", "+=z"], r#"["x","y","z"]"#);
+                expect_json!(["0=x", "+=y"], r#"["x","y"]"#);
+                expect_json!(["1=y", "0=x", "+=z"], r#"["x","y","z"]"#);
+                expect_json!(["0.0=x", "+=y"], r#"[["x"],"y"]"#);
+            }
+
+            #[test]
+            fn append_to_each_array_separately() {
+                expect_json!(["a.+=x", "b.+=y", "a.+=z"], r#"{"a":["x","z"],"b":["y"]}"#);
+                expect_json!(["+.+=x", "+0.+=y", "+.+=z"], r#"[["x","y"],["z"]]"#);
+            }
+
+            #[test]
+            fn refer_to_the_last_element() {
+                expect_json!(
+                    [
+                        "items.+.name=a",
+                        "items.+0.qty:1",
+                        "items.+.name=b",
+                        "items.+0.qty:2"
+                    ],
+                    r#"{"items":[{"name":"a","qty":1},{"name":"b","qty":2}]}"#
+                );
+                expect_json!(["0.a=x", "+0.b=y"], r#"[{"a":"x","b":"y"}]"#);
+            }
+
+            #[test]
+            fn reject_reference_to_the_last_element_of_an_empty_array() {
+                assert_matches!(
+                    check(&["items.+0=x"]),
+                    Err(BuildError::Resolve {
+                        source: PathError {
+                            path,
+                            variant: NoLastIndex,
+                        },
+                        ..
+                    })
+                    if path == new_path("items")
+                );
+            }
+
+            #[test]
+            fn reject_conflicts_with_resolved_indices() {
+                expect_path_error!(["+=x", "+0=y"], "0", ConflictingDirectives);
+                expect_path_error!(
+                    ["2=x", "+=y"],
+                    ".",
+                    IncompleteArray {
+                        index_seen: 2,
+                        index_missing: 0,
+                    }
+                );
+            }
+        }
+
+        mod embedded_values {
+            use super::*;
+
+            #[test]
+            fn merge_embedded_values_with_other_directives() {
+                expect_json!([r#"a:{"b":1}"#, "a.c:2"], r#"{"a":{"b":1,"c":2}}"#);
+                expect_json!(
+                    [r#"a:{"b":{"c":1}}"#, "a.b.d=x"],
+                    r#"{"a":{"b":{"c":1,"d":"x"}}}"#
+                );
+                expect_json!([r#"a:{"b":1}"#, r#"a:{"c":2}"#], r#"{"a":{"b":1,"c":2}}"#);
+                expect_json!(["a:[1,2]", "a.2:3"], r#"{"a":[1,2,3]}"#);
+                expect_json!(["a:[1,2]", "a.+:3"], r#"{"a":[1,2,3]}"#);
+            }
+
+            #[test]
+            fn reject_conflicts_within_embedded_values() {
+                expect_path_error!([r#".:{"a":1,"a":2}"#], "a", ConflictingDirectives);
+                expect_path_error!(
+                    [r#".:{"a":1,"\u0061":2}"#],
+                    ".",
+                    InconsistentKeyEncodings { .. }
+                );
+            }
+
+            #[test]
+            fn reject_conflicts_between_embedded_values_and_other_directives() {
+                expect_path_error!([r#"a:{"b":1}"#, "a.b:2"], "a.b", ConflictingDirectives);
+                expect_path_error!(
+                    [r#"a:{"b":1}"#, "a.0:2"],
+                    "a",
+                    StructuralConflict {
+                        kind1: NodeKind::Object,
+                        kind2: NodeKind::Array,
+                    }
+                );
+                expect_path_error!(
+                    ["a:[]", "a.0:2"],
+                    "a",
+                    StructuralConflict {
+                        kind1: NodeKind::Value,
+                        kind2: NodeKind::Array,
+                    }
+                );
+                expect_path_error!(
+                    ["a:[1]", "a.2:2"],
+                    "a",
+                    IncompleteArray {
+                        index_seen: 2,
+                        index_missing: 1,
+                    }
+                );
+            }
+        }
+
+        mod groups {
+            use super::*;
+
+            #[test]
+            fn prefix_grouped_directives() {
+                expect_json!(
+                    ["a.b.{", "c=x", "d:1", "}"],
+                    r#"{"a":{"b":{"c":"x","d":1}}}"#
+                );
+                expect_json!(["a.{", "}", "b=x"], r#"{"b":"x"}"#);
+            }
+
+            #[test]
+            fn nest_groups() {
+                expect_json!(
+                    ["a.{", "b.{", "c=x", "}", "d=y", "}", "e=z"],
+                    r#"{"a":{"b":{"c":"x"},"d":"y"},"e":"z"}"#
+                );
+            }
+
+            #[test]
+            fn resolve_relative_indices_of_group_once() {
+                expect_json!(
+                    ["items.+.{", "a=x", "b=y", "}", "items.+.{", "a=z", "}"],
+                    r#"{"items":[{"a":"x","b":"y"},{"a":"z"}]}"#
+                );
+                expect_json!(["a.{", "+=x", "+=y", "}"], r#"{"a":["x","y"]}"#);
+            }
+
+            #[test]
+            fn reject_conflicts_by_full_path() {
+                expect_path_error!(["a.{", "b=x", "}", "a.b=y"], "a.b", ConflictingDirectives);
+                expect_path_error!(
+                    ["a.{", "0=x", "}", "a.b=y"],
+                    "a",
+                    StructuralConflict {
+                        kind1: NodeKind::Array,
+                        kind2: NodeKind::Object,
+                    }
+                );
+            }
+
+            #[test]
+            fn reject_unbalanced_groups() {
+                assert_matches!(
+                    check(&["a=x", "}"]),
+                    Err(BuildError::UnbalancedGroup { directive }) if directive == "}"
+                );
+                assert_matches!(
+                    check(&["a.{", "b=x"]),
+                    Err(BuildError::UnclosedGroup { directive }) if directive == "a.{"
+                );
+            }
+
+            #[test]
+            fn reject_malformed_groups() {
+                expect_syntax_error!([".{"], UnexpectedChar { pos: 2, ch: '{' });
+                expect_syntax_error!(["a.{x", "}"], UnexpectedEndOfString);
+                expect_syntax_error!(["a{", "}"], UnexpectedChar { pos: 2, ch: '{' });
+                expect_syntax_error!(["a.{=x"], UnexpectedChar { pos: 4, ch: '=' });
+            }
+        }
+
+        mod expansion {
+            use super::*;
+
+            #[test]
+            fn expand_into_every_combination() {
+                expect_json!(
+                    ["{a,b}.{0..1}.{x,y}:1"],
+                    r#"{"a":[{"x":1,"y":1},{"x":1,"y":1}],"b":[{"x":1,"y":1},{"x":1,"y":1}]}"#
+                );
+            }
+
+            #[test]
+            fn expand_json_values() {
+                expect_json!(
+                    [r#"servers.0..1:{"port":80,"tags":[]}"#],
+                    r#"{"servers":[{"port":80,"tags":[]},{"port":80,"tags":[]}]}"#
+                );
+            }
+
+            #[test]
+            fn satisfy_array_completeness_with_ranges() {
+                expect_json!(
+                    ["items.0..2.qty:1", "items.3.qty:2"],
+                    r#"{"items":[{"qty":1},{"qty":1},{"qty":1},{"qty":2}]}"#
+                );
+                expect_path_error!(
+                    ["0..1=x", "3=y"],
+                    ".",
+                    IncompleteArray {
+                        index_seen: 3,
+                        index_missing: 2
+                    }
+                );
+            }
+
+            #[test]
+            fn resolve_relative_indices_once_per_expanded_path() {
+                expect_json!(["+.{a,b}=x", "+.a=y"], r#"[{"a":"x","b":"x"},{"a":"y"}]"#);
+                expect_json!(
+                    ["{a,b}.+.k=x", "a.+.k=y", "{a,b}.+0.l=z"],
+                    r#"{"a":[{"k":"x"},{"k":"y","l":"z"}],"b":[{"k":"x","l":"z"}]}"#
+                );
+            }
+
+            #[test]
+            fn expand_group_paths() {
+                expect_json!(
+                    ["{a,b}.{", "{x,y}=1", "}"],
+                    r#"{"a":{"x":"1","y":"1"},"b":{"x":"1","y":"1"}}"#
+                );
+                expect_json!(["0..1.{", "+=x", "}"], r#"[["x"],["x"]]"#);
+            }
+
+            #[test]
+            fn report_conflicts_against_unexpanded_directive() {
+                assert_matches!(
+                    check(&["a.1=x", "a.0..2=y"]),
+                    Err(BuildError::Path { source, directive })
+                    if directive == "a.0..2=y" && source.path == new_path("a.1")
+                );
+                assert_matches!(
+                    check(&["{a,a}=x"]),
+                    Err(BuildError::Path { source, directive })
+                    if directive == "{a,a}=x" && source.path == new_path("a")
+                );
+            }
+        }
+
+        mod base_documents {
+            use super::*;
+
+            fn check_base(base: &str, directives: &[&str]) -> BuildResult<String> {
+                let base: Node = base.parse().unwrap();
+                let directives = directives.iter().map(|s| s.bytes().collect::<Vec<u8>>());
+                apply(&base, directives, &Options::default())
+                    .map(|tree| tree.map(|node| node.to_string()).unwrap_or_default())
+            }
+
+            #[test]
+            fn keep_base_without_directives() {
+                let base = r#"{ "b": [1.0e2, {}], "a": "A" }"#;
+                assert_eq!(
+                    check_base(base, &[]).unwrap(),
+                    r#"{"a":"A","b":[1.0e2,{}]}"#
+                );
+            }
+
+            #[test]
+            fn replace_subtrees() {
+                let base = r#"{"user":{"name":"A","email":"a@x"},"n":1}"#;
+                assert_eq!(
+                    check_base(base, &["user.email=b@y"]).unwrap(),
+                    r#"{"n":1,"user":{"email":"b@y","name":"A"}}"#
+                );
+                assert_eq!(
+                    check_base(base, &[r#"user:{"id":2}"#]).unwrap(),
+                    r#"{"n":1,"user":{"id":2}}"#
+                );
+                assert_eq!(check_base(base, &[".:[]"]).unwrap(), "[]");
+                assert_eq!(
+                    check_base(base, &["user.name:{}", "user.name.x=y"])
+                        .unwrap_err()
+                        .to_string(),
+                    r#"directive "user.name.x=y": path user.name: path referred to as both value and object"#
+                );
+            }
+
+            #[test]
+            fn reject_duplicate_keys_in_base() {
+                let parse = |base: &str| base.parse::<Node>().map_err(|e| e.to_string());
+                assert_eq!(
+                    parse(r#"{"a":1,"a":2}"#).unwrap_err(),
+                    "path a: duplicate key"
+                );
+                assert_eq!(
+                    parse(r#"{"a":{"J":1,"\u004a":2}}"#).unwrap_err(),
+                    r#"path a."\u004a": duplicate key"#
+                );
+                assert_eq!(
+                    parse(r#"[{"a":1,"a":{}}]"#).unwrap_err(),
+                    "path 0.a: duplicate key"
+                );
+                assert_eq!(
+                    parse(r#"{"\n😀":1,"\u000a\ud83d\ude00":2}"#).unwrap_err(),
+                    r#"path "\u000a\ud83d\ude00": duplicate key"#
+                );
+                assert!(parse(r#"{"a":{"b":1},"b":{"a":2}}"#).is_ok());
+            }
+
+            #[test]
+            fn keep_key_order_of_base() {
+                let base: Node = r#"{"b":1,"a":{"z":1,"y":2},"c":0}"#.parse().unwrap();
+                let directives = [r#"a:{"q":1,"y":5}"#, "d=1", "b=x"]
+                    .iter()
+                    .map(|s| s.bytes().collect::<Vec<u8>>());
+                let tree = apply(&base, directives, &Options::default())
+                    .unwrap()
+                    .unwrap();
+                let style = crate::output::Style {
+                    preserve_order: true,
+                    ..crate::output::Style::default()
+                };
+                assert_eq!(
+                    crate::output::to_json(&tree, &style),
+                    r#"{"b":"x","a":{"y":5,"q":1},"c":0,"d":"1"}"#
+                );
+            }
+
+            #[test]
+            fn extend_base() {
+                let base = r#"{"tags":["a"],"empty":[],"none":{}}"#;
+                assert_eq!(
+                    check_base(base, &["tags.+=b", "empty.+=c", "none.x=d", "y=e"]).unwrap(),
+                    r#"{"empty":["c"],"none":{"x":"d"},"tags":["a","b"],"y":"e"}"#
+                );
+                assert_eq!(
+                    check_base(r#"[{"a":1}]"#, &["+0.b:2"]).unwrap(),
+                    r#"[{"a":1,"b":2}]"#
+                );
+            }
+
+            #[test]
+            fn reject_descending_into_base_as_other_kind() {
+                let base = r#"{"user":{"name":"A"},"tags":[],"n":1}"#;
+                assert_matches!(
+                    check_base(base, &["user.0=x"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            path,
+                            variant: StructuralConflict {
+                                kind1: NodeKind::Object,
+                                kind2: NodeKind::Array,
+                            },
+                        },
+                        ..
+                    }) if path == new_path("user")
+                );
+                assert_matches!(
+                    check_base(base, &["tags.x=y"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: StructuralConflict {
+                                kind1: NodeKind::Array,
+                                kind2: NodeKind::Object,
+                            },
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check_base(base, &["n.x=y"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: StructuralConflict {
+                                kind1: NodeKind::Value,
+                                kind2: NodeKind::Object,
+                            },
+                            ..
+                        },
+                        ..
+                    })
+                );
+            }
+
+            #[test]
+            fn validate_directives_together_with_base() {
+                let base = r#"{"items":[1,2],"a":1}"#;
+                assert_matches!(
+                    check_base(base, &["x=1", "x=2"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: ConflictingDirectives,
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check_base(base, &["items.3:3"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: IncompleteArray {
+                                index_seen: 3,
+                                index_missing: 2
+                            },
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check_base(r#"{"\u0061":1}"#, &["a=x"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: InconsistentKeyEncodings { .. },
+                            ..
+                        },
+                        ..
+                    })
+                );
+            }
+        }
+
+        mod edits {
+            use super::*;
+
+            const BASE: &str = r#"{"user":{"name":"A","email":"a@x"},"items":["a","b","c"]}"#;
+
+            fn check_edits(directives: &[&str]) -> BuildResult<String> {
+                let base: Node = BASE.parse().unwrap();
+                let directives = directives.iter().map(|s| s.bytes().collect::<Vec<u8>>());
+                apply(&base, directives, &Options::default())
+                    .map(|tree| tree.map(|node| node.to_string()).unwrap_or_default())
+            }
+
+            #[test]
+            fn delete_paths() {
+                assert_eq!(
+                    check_edits(&["!user.email"]).unwrap(),
+                    r#"{"items":["a","b","c"],"user":{"name":"A"}}"#
+                );
+                assert_eq!(check_edits(&["!user", "!items"]).unwrap(), "{}");
+                assert_eq!(check_edits(&["!."]).unwrap(), "");
+                assert_eq!(check_edits(&["!.", "a=x"]).unwrap(), r#"{"a":"x"}"#);
+            }
+
+            #[test]
+            fn reindex_arrays_after_deletion() {
+                assert_eq!(
+                    check_edits(&["!items.0", "items.+=d"]).unwrap(),
+                    r#"{"items":["b","c","d"],"user":{"email":"a@x","name":"A"}}"#
+                );
+                assert_eq!(
+                    check_edits(&["!items.0", "!items.0"]).unwrap(),
+                    r#"{"items":["c"],"user":{"email":"a@x","name":"A"}}"#
+                );
+                assert_eq!(
+                    check_edits(&["!items.{0,2}"]).unwrap(),
+                    r#"{"items":["b"],"user":{"email":"a@x","name":"A"}}"#
+                );
+                assert_eq!(
+                    check_edits(&["!items.+0", "!items.0..1"]).unwrap(),
+                    r#"{"items":[],"user":{"email":"a@x","name":"A"}}"#
+                );
+            }
+
+            #[test]
+            fn reject_deleting_missing_paths() {
+                assert_matches!(
+                    check_edits(&["!user.phone"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            path,
+                            variant: NoSuchPath,
+                        },
+                        ..
+                    }) if path == new_path("user.phone")
+                );
+                assert_matches!(
+                    check_edits(&["!items.3"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: NoSuchPath,
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check_edits(&["!user.email", "!user.email"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: NoSuchPath,
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check(&["!a"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: NoSuchPath,
+                            ..
+                        },
+                        ..
+                    })
+                );
+            }
+
+            #[test]
+            fn ignore_deleting_missing_paths_on_request() {
+                let options = Options {
+                    ignore_missing: true,
+                    ..Options::default()
+                };
+                let directives = ["!a.b", "c=x"].map(|s| s.bytes().collect::<Vec<u8>>());
+                assert_eq!(
+                    compose_with(directives.into_iter(), &options)
+                        .unwrap()
+                        .map(|node| node.to_string()),
+                    Some(r#"{"c":"x"}"#.to_string())
+                );
+            }
+
+            #[test]
+            fn copy_subtrees() {
+                assert_eq!(
+                    check_edits(&["owner#copy=user", "owner.name=B"]).unwrap(),
+                    r#"{"items":["a","b","c"],"owner":{"email":"a@x","name":"B"},"user":{"email":"a@x","name":"A"}}"#
+                );
+                assert_eq!(
+                    check_edits(&["items.+#copy=items.0", "!user"]).unwrap(),
+                    r#"{"items":["a","b","c","a"]}"#
+                );
+                assert_eq!(
+                    check_edits(&["!user", "{x,y}#copy=items.1"]).unwrap(),
+                    r#"{"items":["a","b","c"],"x":"b","y":"b"}"#
+                );
+            }
+
+            #[test]
+            fn move_subtrees() {
+                assert_eq!(
+                    check_edits(&["user.mail#move=user.email", "!items"]).unwrap(),
+                    r#"{"user":{"mail":"a@x","name":"A"}}"#
+                );
+                assert_eq!(
+                    check_edits(&["items.+#move=items.0", "!user"]).unwrap(),
+                    r#"{"items":["b","c","a"]}"#
+                );
+                assert_eq!(
+                    check_edits(&["user.{", "items#move=items", "}", "!user.email"]).unwrap(),
+                    r#"{"user":{"items":["a","b","c"],"name":"A"}}"#
+                );
+            }
+
+            #[test]
+            fn reject_invalid_copies() {
+                assert_matches!(
+                    check_edits(&["x#copy=nope"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: NoSuchPath,
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check_edits(&["items.5#copy=user"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: IncompleteArray {
+                                index_seen: 5,
+                                index_missing: 3
+                            },
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check_edits(&["user.name.x#copy=items"]),
+                    Err(BuildError::Path {
+                        source: PathError {
+                            variant: StructuralConflict {
+                                kind1: NodeKind::Value,
+                                kind2: NodeKind::Object,
+                            },
+                            ..
+                        },
+                        ..
+                    })
+                );
+                assert_matches!(
+                    check_edits(&["x#copy=items.0..1"]),
+                    Err(BuildError::AmbiguousSource { .. })
+                );
+                expect_syntax_error!(["x#copy=a b"], UnexpectedChar { pos: 9, ch: ' ' });
+                expect_syntax_error!(["!a=x"], UnexpectedChar { pos: 3, ch: '=' });
+            }
+
+            #[test]
+            fn reject_edits_after_assignments() {
+                assert_matches!(
+                    check_edits(&["a=x", "!user"]),
+                    Err(BuildError::EditAfterAssignment { directive }) if directive == "!user"
+                );
+                assert_matches!(
+                    check_edits(&["a=x", "b#copy=user"]),
+                    Err(BuildError::EditAfterAssignment { .. })
+                );
+            }
+        }
+
         mod merging {
             use super::*;
 
@@ -695,10 +2385,11 @@ mod tests {
             use super::*;
 
             #[test]
-            #[ignore] // FIXME
             fn remove_unnecessary_whitespace_in_values() {
                 expect_json!([".: \t\n\r{ \t\n\r} \t\n\r"], "{}");
                 expect_json!([".: \t\n\r[ \t\n\r] \t\n\r"], "[]");
+                expect_json!([".: 42 "], "42");
+                expect_json!([r#".:{ "a" : [ 1 , " x " ] }"#], r#"{"a":[1," x "]}"#);
             }
 
             #[test]
@@ -786,6 +2477,78 @@ mod tests {
         }
     }
 
+    mod locations {
+        use super::*;
+        use crate::input::Input;
+        use crate::input::Location;
+
+        fn check_located(directive: &str) -> BuildResult<Option<Node>> {
+            compose(
+                [Input {
+                    bytes: directive.bytes().collect(),
+                    location: Some(Location {
+                        file: Rc::new("recipe.args".to_string()),
+                        line: 3,
+                        column: 5,
+                    }),
+                }]
+                .into_iter(),
+            )
+        }
+
+        #[test]
+        fn report_location_of_syntax_errors() {
+            let error = check_located("a.b:tru").unwrap_err();
+            assert_matches!(&error, BuildError::Located { location, .. } if location.column == 9);
+            assert_eq!(
+                error.to_string(),
+                r#"recipe.args:3:9: directive "a.b:tru": position 5: invalid json value"#
+            );
+        }
+
+        #[test]
+        fn report_location_of_syntax_errors_in_multi_line_directives() {
+            assert_matches!(
+                check_located("a:[1,\n2,]"),
+                Err(BuildError::Located { location, .. })
+                if location.line == 3 && location.column == 7
+            );
+            assert_matches!(
+                check_located("a:[1,\n2] x"),
+                Err(BuildError::Located { location, .. })
+                if location.line == 4 && location.column == 4
+            );
+        }
+
+        #[test]
+        fn report_location_of_other_directive_errors() {
+            assert_matches!(
+                check_located("a.+0=x"),
+                Err(BuildError::Located { location, source })
+                if location.column == 5 && matches!(*source, BuildError::Resolve { .. })
+            );
+        }
+
+        #[test]
+        fn report_location_of_validation_errors() {
+            let inputs = ["a.0..2=x", "a.{1,3}=y"]
+                .into_iter()
+                .enumerate()
+                .map(|(i, s)| Input {
+                    bytes: s.bytes().collect(),
+                    location: Some(Location {
+                        file: Rc::new("recipe.args".to_string()),
+                        line: i + 1,
+                        column: 1,
+                    }),
+                });
+            assert_eq!(
+                compose(inputs).unwrap_err().to_string(),
+                r#"recipe.args:2:1: directive "a.{1,3}=y": path a.1: conflicting directives"#
+            );
+        }
+    }
+
     // these are good candidates for howto guides, but deemed redundant in the context of unit
     // tests.
     mod howto {
@@ -0,0 +1,254 @@
+use crate::directive::Path;
+use crate::directive::Segment;
+use crate::directive::decode_string;
+use crate::node::Node;
+use crate::output::assignment;
+use crate::validator::NodeKind;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+/// A difference between two trees.
+#[derive(Debug)]
+pub enum Change<'a> {
+    /// A subtree that only the old tree has
+    Removed { path: Rc<Path>, old: &'a Node },
+    /// A subtree that only the new tree has
+    Added { path: Rc<Path>, new: &'a Node },
+    /// A value, or a subtree of another kind, that the new tree has in place of the old one
+    Changed {
+        path: Rc<Path>,
+        old: &'a Node,
+        new: &'a Node,
+    },
+}
+
+/// Compares two trees, object member by object member and array element by array element.
+///
+/// Changes are listed in path order, with object keys sorted.
+/// Keys and strings are compared by what their escape sequences decode to, but other scalar
+/// values are compared by their text, so e.g. `1.0` and `1` are different.
+/// The paths of changes use the keys as the old tree writes them, unless only the new tree has
+/// them.
+pub fn diff<'a>(old: &'a Node, new: &'a Node) -> Vec<Change<'a>> {
+    fn aux<'a>(path: Rc<Path>, old: &'a Node, new: &'a Node, changes: &mut Vec<Change<'a>>) {
+        if let (Node::Value(old_value), Node::Value(new_value)) = (old, new)
+            && same_value(old_value, new_value)
+        {
+            return;
+        }
+        let (old_kind, old_children) = children(old);
+        let (new_kind, new_children) = children(new);
+        if old_kind != new_kind || old_kind == NodeKind::Value {
+            changes.push(Change::Changed { path, old, new });
+            return;
+        }
+        let decoded: BTreeSet<&Segment> = old_children.keys().chain(new_children.keys()).collect();
+        for decoded in decoded {
+            match (old_children.get(decoded), new_children.get(decoded)) {
+                (Some((segment, old)), Some((_, new))) => {
+                    aux(path.append(segment.clone()), old, new, changes)
+                }
+                (Some((segment, old)), None) => changes.push(Change::Removed {
+                    path: path.append(segment.clone()),
+                    old,
+                }),
+                (None, Some((segment, new))) => changes.push(Change::Added {
+                    path: path.append(segment.clone()),
+                    new,
+                }),
+                (None, None) => unreachable!("segments are taken from either tree"),
+            }
+        }
+    }
+    let mut changes = vec![];
+    aux(Path::root(), old, new, &mut changes);
+    changes
+}
+
+/// Tells whether two values are the same, taking strings as what their escape sequences decode
+/// to.
+fn same_value(old: &str, new: &str) -> bool {
+    fn decode(value: &str) -> Option<String> {
+        value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .map(decode_string)
+    }
+    old == new || decode(old).is_some_and(|old| decode(new) == Some(old))
+}
+
+/// Returns the kind of a node along with its children and their segments, keyed by the
+/// segments with their escape sequences decoded.
+///
+/// Empty objects and arrays may be stored as values, and are taken as what they are.
+fn children(node: &Node) -> (NodeKind, BTreeMap<Segment, (Segment, &Node)>) {
+    match node {
+        Node::Object(members) => (
+            NodeKind::Object,
+            members
+                .iter()
+                .map(|(key, child)| {
+                    let decoded = Segment::Key(Rc::new(decode_string(key)));
+                    (decoded, (Segment::Key(key.clone()), child))
+                })
+                .collect(),
+        ),
+        Node::Array(elements) => (
+            NodeKind::Array,
+            elements
+                .iter()
+                .map(|(index, child)| (Segment::Index(*index), (Segment::Index(*index), child)))
+                .collect(),
+        ),
+        Node::Value(value) if value == "{}" => (NodeKind::Object, BTreeMap::new()),
+        Node::Value(value) if value == "[]" => (NodeKind::Array, BTreeMap::new()),
+        Node::Value(_) => (NodeKind::Value, BTreeMap::new()),
+    }
+}
+
+/// Describes changes one leaf per line.
+///
+/// Lines start with `-` for removed leaves, `+` for added leaves and `~` for changed values.
+pub fn to_report(changes: &[Change]) -> Vec<String> {
+    let mut lines = vec![];
+    for change in changes {
+        match change {
+            Change::Removed { path, old } => lines.extend(
+                old.to_directives_at(path.clone())
+                    .into_iter()
+                    .map(|leaf| format!("- {}: {}", leaf.path, leaf.value)),
+            ),
+            Change::Added { path, new } => lines.extend(
+                new.to_directives_at(path.clone())
+                    .into_iter()
+                    .map(|leaf| format!("+ {}: {}", leaf.path, leaf.value)),
+            ),
+            Change::Changed { path, old, new } => {
+                lines.push(format!("~ {}: {} -> {}", path, old, new));
+            }
+        }
+    }
+    lines
+}
+
+/// Returns directives that turn the old tree into the new one when applied to it.
+///
+/// Removed subtrees are deleted first, in reverse order so that deleting array elements does
+/// not shift the indices of elements that are yet to be deleted.
+pub fn to_directives(changes: &[Change]) -> Vec<String> {
+    let mut directives: Vec<_> = changes
+        .iter()
+        .rev()
+        .filter_map(|change| match change {
+            Change::Removed { path, .. } => Some(format!("!{}", path)),
+            _ => None,
+        })
+        .collect();
+    directives.extend(changes.iter().filter_map(|change| match change {
+        Change::Added { path, new } | Change::Changed { path, new, .. } => {
+            Some(assignment(path, &new.to_string()))
+        }
+        Change::Removed { .. } => None,
+    }));
+    directives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composer::Options;
+    use crate::composer::apply;
+
+    const OLD: &str = r#"{"a":[1,2,3],"b":{"c":true,"d":"x"},"e":1,"f":{"g":[]}}"#;
+    const NEW: &str = r#"{"a":[1,5],"b":{"c":true,"h":[null,{}]},"e":{"i":1},"f":{"g":[]}}"#;
+
+    #[test]
+    fn report_changed_leaves() {
+        let (old, new) = (OLD.parse().unwrap(), NEW.parse().unwrap());
+        assert_eq!(
+            to_report(&diff(&old, &new)),
+            [
+                "~ a.1: 2 -> 5",
+                "- a.2: 3",
+                r#"- b.d: "x""#,
+                "+ b.h.0: null",
+                "+ b.h.1: {}",
+                r#"~ e: 1 -> {"i":1}"#,
+            ]
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn turn_old_tree_into_new_tree() {
+        let (old, new) = (OLD.parse().unwrap(), NEW.parse().unwrap());
+        let directives = to_directives(&diff(&old, &new));
+        assert_eq!(
+            directives,
+            ["!b.d", "!a.2", "a.1:5", "b.h:[null,{}]", r#"e:{"i":1}"#]
+        );
+        let tree = apply(
+            &old,
+            directives.into_iter().map(String::into_bytes),
+            &Options::default(),
+        );
+        assert_eq!(tree.unwrap().unwrap().to_string(), NEW);
+    }
+
+    #[test]
+    fn descend_into_empty_containers() {
+        let old = r#"{"a":{},"b":[],"c":{},"d":[1]}"#.parse().unwrap();
+        let new = r#"{"a":{"x":1},"b":[true],"c":[],"d":[]}"#.parse().unwrap();
+        let changes = diff(&old, &new);
+        assert_eq!(
+            to_report(&changes),
+            ["+ a.x: 1", "+ b.0: true", "~ c: {} -> []", "- d.0: 1"]
+        );
+        let directives = to_directives(&changes);
+        assert_eq!(directives, ["!d.0", "a.x:1", "b.0:true", "c:[]"]);
+        let tree = apply(
+            &old,
+            directives.into_iter().map(String::into_bytes),
+            &Options::default(),
+        );
+        assert_eq!(tree.unwrap().unwrap().to_string(), new.to_string());
+    }
+
+    #[test]
+    fn compare_keys_and_strings_by_their_decoded_text() {
+        let old = r#"{"\u0061":"\u00e9","b":{"c\n":1},"d":"\\u0041"}"#
+            .parse()
+            .unwrap();
+        let same = r#"{"a":"é","b":{"c\u000a":1},"d":"\\u0041"}"#.parse().unwrap();
+        assert!(diff(&old, &same).is_empty());
+
+        let new = r#"{"a":"é","b":{"c\u000a":2},"d":"\u0041"}"#.parse().unwrap();
+        let changes = diff(&old, &new);
+        assert_eq!(
+            to_report(&changes),
+            [r#"~ b."c\n": 1 -> 2"#, r#"~ d: "\\u0041" -> "\u0041""#]
+        );
+        let directives = to_directives(&changes);
+        assert_eq!(directives, [r#"b."c\n":2"#, r#"d:"\u0041""#]);
+        let tree = apply(
+            &old,
+            directives.into_iter().map(String::into_bytes),
+            &Options::default(),
+        );
+        assert!(diff(&tree.unwrap().unwrap(), &new).is_empty());
+    }
+
+    #[test]
+    fn delete_array_elements_from_the_end() {
+        let (old, new) = ("[[0,1,2],3]".parse().unwrap(), "[[0]]".parse().unwrap());
+        let directives = to_directives(&diff(&old, &new));
+        assert_eq!(directives, ["!1", "!0.2", "!0.1"]);
+        let tree = apply(
+            &old,
+            directives.into_iter().map(String::into_bytes),
+            &Options::default(),
+        );
+        assert_eq!(tree.unwrap().unwrap().to_string(), "[[0]]");
+    }
+}
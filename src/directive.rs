@@ -1,8 +1,14 @@
 use crate::parser::DirectiveAst;
 use crate::parser::OperatorAst;
 use crate::parser::SegmentAst;
+use crate::parser::SourceAst;
 use crate::parser::is_xid_string;
+use crate::parser::split_integer;
+use crate::validator::NodeKind;
+use crate::validator::PathError;
+use crate::validator::PathErrorVariant;
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::collections::VecDeque;
 use std::rc::Rc;
 
@@ -12,18 +18,130 @@ pub struct Directive {
     pub value: String,
 }
 
-impl From<DirectiveAst> for Directive {
-    fn from(ast: DirectiveAst) -> Self {
+impl Directive {
+    /// Converts a directive into one directive per leaf of its value.
+    ///
+    /// Non-empty objects and arrays in JSON values are broken down into their members, so that
+    /// they can be merged with other directives.
+    pub fn from_ast(ast: DirectiveAst) -> Vec<Directive> {
+        debug_assert!(
+            ast.source == SourceAst::Inline,
+            "caller is responsible for loading values"
+        );
         let path = ast.path.into_iter().map(|segment| segment.into()).collect();
-        let value = if ast.operator == OperatorAst::Colon {
-            ast.value
+        let mut directives = vec![];
+        let value = match ast.operator {
+            OperatorAst::Colon => {
+                split_json(path, &ast.value, &mut directives);
+                return directives;
+            }
+            OperatorAst::EqualSign => format!(r#""{}""#, escape_string(&ast.value)),
+            OperatorAst::Number | OperatorAst::Boolean => ast.value,
+            OperatorAst::Integer => normalize_integer(&ast.value),
+            OperatorAst::Base64 => format!(
+                r#""{}""#,
+                encode_base64(&decode_hex(&ast.value), BASE64_ALPHABET, true)
+            ),
+            OperatorAst::Base64Url => format!(
+                r#""{}""#,
+                encode_base64(&decode_hex(&ast.value), BASE64URL_ALPHABET, false)
+            ),
+            OperatorAst::Template => panic!("caller is responsible for interpolating variables"),
+            OperatorAst::Copy | OperatorAst::Move => {
+                panic!("caller is responsible for copying and moving subtrees")
+            }
+        };
+        directives.push(Directive { path, value });
+        directives
+    }
+}
+
+const JSON_WHITESPACE: [char; 4] = [' ', '\t', '\n', '\r'];
+
+fn split_json<'a>(path: Rc<Path>, input: &'a str, directives: &mut Vec<Directive>) -> &'a str {
+    let input = input.trim_start_matches(JSON_WHITESPACE);
+    if let Some(rest) = input.strip_prefix('{') {
+        let mut rest = rest.trim_start_matches(JSON_WHITESPACE);
+        if let Some(rest) = rest.strip_prefix('}') {
+            directives.push(Directive {
+                path,
+                value: "{}".to_string(),
+            });
+            return rest;
+        }
+        loop {
+            let (key, after_key) = split_json_string(rest);
+            let key = Segment::Key(Rc::new(key[1..key.len() - 1].to_string()));
+            let after_colon = after_key
+                .trim_start_matches(JSON_WHITESPACE)
+                .strip_prefix(':')
+                .expect("caller is responsible for only splitting valid JSON");
+            rest = split_json(path.append(key), after_colon, directives)
+                .trim_start_matches(JSON_WHITESPACE);
+            match rest.strip_prefix(',') {
+                Some(after_comma) => rest = after_comma.trim_start_matches(JSON_WHITESPACE),
+                None => {
+                    return rest
+                        .strip_prefix('}')
+                        .expect("caller is responsible for only splitting valid JSON");
+                }
+            }
+        }
+    } else if let Some(rest) = input.strip_prefix('[') {
+        let mut rest = rest.trim_start_matches(JSON_WHITESPACE);
+        if let Some(rest) = rest.strip_prefix(']') {
+            directives.push(Directive {
+                path,
+                value: "[]".to_string(),
+            });
+            return rest;
+        }
+        for index in 0.. {
+            rest = split_json(path.append(Segment::Index(index)), rest, directives)
+                .trim_start_matches(JSON_WHITESPACE);
+            match rest.strip_prefix(',') {
+                Some(after_comma) => rest = after_comma,
+                None => break,
+            }
+        }
+        rest.strip_prefix(']')
+            .expect("caller is responsible for only splitting valid JSON")
+    } else {
+        let (value, rest) = if input.starts_with('"') {
+            split_json_string(input)
         } else {
-            format!(r#""{}""#, escape_string(&ast.value))
+            let end = input
+                .find(|c: char| JSON_WHITESPACE.contains(&c) || matches!(c, ',' | ']' | '}'))
+                .unwrap_or(input.len());
+            input.split_at(end)
         };
-        Directive { path, value }
+        directives.push(Directive {
+            path,
+            value: value.to_string(),
+        });
+        rest
     }
 }
 
+fn split_json_string(input: &str) -> (&str, &str) {
+    let mut escaped = false;
+    let end = input
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| {
+            if escaped {
+                escaped = false;
+                false
+            } else {
+                escaped = c == '\\';
+                c == '"'
+            }
+        })
+        .map(|(i, _)| i + 1)
+        .expect("caller is responsible for only splitting valid JSON");
+    input.split_at(end)
+}
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Segment {
     Index(u32),
@@ -31,6 +149,14 @@ pub enum Segment {
 }
 
 impl Segment {
+    /// The kind of node that the segment refers into.
+    pub fn kind(&self) -> NodeKind {
+        match self {
+            Segment::Key(_) => NodeKind::Object,
+            Segment::Index(_) => NodeKind::Array,
+        }
+    }
+
     pub fn unescape(&self) -> Segment {
         match self {
             Segment::Key(key) => Segment::Key(Rc::new(unescape_string(key))),
@@ -40,7 +166,7 @@ impl Segment {
 
     pub fn as_unquoted(&self) -> Option<&str> {
         match self {
-            Segment::Key(key) => Some(&key),
+            Segment::Key(key) => Some(key),
             _ => None,
         }
     }
@@ -69,11 +195,87 @@ impl From<SegmentAst> for Segment {
                 Segment::Key(Rc::new(quoted[1..quoted.len() - 1].to_string()))
             }
             SegmentAst::BareKey(bare) => Segment::Key(Rc::new(escape_string(&bare))),
+            SegmentAst::NextIndex | SegmentAst::LastIndex => {
+                panic!("caller is responsible for resolving relative indices")
+            }
+            SegmentAst::Range(..) | SegmentAst::Alternatives(_) => {
+                panic!("caller is responsible for expanding brace lists and ranges")
+            }
         }
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq)]
+/// Expands the brace lists and ranges of a path into every concrete path they denote.
+///
+/// The concrete paths are produced in the order the alternatives are written, with the last
+/// segment varying fastest.
+pub fn expand_path(segments: &[SegmentAst]) -> Vec<Vec<SegmentAst>> {
+    fn alternatives(segment: &SegmentAst) -> Vec<SegmentAst> {
+        match segment {
+            SegmentAst::Range(start, end) => (*start..=*end).map(SegmentAst::ArrayIndex).collect(),
+            SegmentAst::Alternatives(items) => items.iter().flat_map(alternatives).collect(),
+            _ => vec![segment.clone()],
+        }
+    }
+
+    let mut paths = vec![vec![]];
+    for segment in segments {
+        let alternatives = alternatives(segment);
+        paths = paths
+            .into_iter()
+            .flat_map(|path: Vec<SegmentAst>| {
+                alternatives.iter().map(move |alternative| {
+                    let mut path = path.clone();
+                    path.push(alternative.clone());
+                    path
+                })
+            })
+            .collect();
+    }
+    paths
+}
+
+/// Resolves the relative `+` and `+0` segments into concrete array indices.
+///
+/// Every directive must be recorded after it has been resolved, so that subsequent `+` segments
+/// are assigned the next free index of their array.
+#[derive(Debug, Default)]
+pub struct IndexAllocator {
+    lengths: HashMap<Rc<Path>, u32>,
+}
+
+impl IndexAllocator {
+    pub fn resolve(&self, segments: &mut [SegmentAst]) -> Result<(), PathError> {
+        let mut path = Path::root();
+        for segment in segments.iter_mut() {
+            let length = self.lengths.get(&path).copied();
+            match segment {
+                SegmentAst::NextIndex => *segment = SegmentAst::ArrayIndex(length.unwrap_or(0)),
+                SegmentAst::LastIndex => match length {
+                    Some(length) => *segment = SegmentAst::ArrayIndex(length - 1),
+                    None => Err(PathError {
+                        path: path.clone(),
+                        variant: PathErrorVariant::NoLastIndex,
+                    })?,
+                },
+                _ => {}
+            }
+            path = path.append(segment.clone());
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, path: &Rc<Path>) {
+        for (prefix, segment) in path.iter() {
+            if let Segment::Index(index) = segment {
+                let length = self.lengths.entry(prefix).or_default();
+                *length = (*length).max(index + 1);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Path {
     Root,
     Append(Rc<Path>, Segment),
@@ -109,13 +311,9 @@ impl Path {
             segments.push_front(segment);
             path = prefix;
         }
-        match segments.pop_front() {
-            None => None,
-            Some(first) => Some((
-                (*first).clone(),
-                segments.iter().cloned().cloned().collect(),
-            )),
-        }
+        segments
+            .pop_front()
+            .map(|first| (first.clone(), segments.iter().cloned().cloned().collect()))
     }
 
     pub fn unescape(self: &Rc<Self>) -> Rc<Self> {
@@ -135,24 +333,34 @@ impl Path {
             Path::Append(prefix, _) => prefix.len() + 1,
         }
     }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Path::Root)
+    }
 }
 
-impl PartialOrd for Path {
-    fn partial_cmp(&self, rhs: &Self) -> Option<std::cmp::Ordering> {
+impl Ord for Path {
+    fn cmp(&self, rhs: &Self) -> Ordering {
         match (self, rhs) {
-            (Path::Root, Path::Root) => Some(Ordering::Equal),
-            (Path::Root, _) => Some(Ordering::Greater),
-            (_, Path::Root) => Some(Ordering::Less),
+            (Path::Root, Path::Root) => Ordering::Equal,
+            (Path::Root, _) => Ordering::Greater,
+            (_, Path::Root) => Ordering::Less,
             (Path::Append(lhs_prefix, lhs_segment), Path::Append(rhs_prefix, rhs_segment)) => {
                 match lhs_prefix.cmp(rhs_prefix) {
-                    Ordering::Equal => lhs_segment.partial_cmp(rhs_segment),
-                    ordering => Some(ordering),
+                    Ordering::Equal => lhs_segment.cmp(rhs_segment),
+                    ordering => ordering,
                 }
             }
         }
     }
 }
 
+impl PartialOrd for Path {
+    fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
 impl std::fmt::Display for Path {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         if let Some((prefix, segment)) = self.split_last() {
@@ -211,7 +419,65 @@ fn nibble_to_hex(n: u8) -> char {
     }
 }
 
-fn escape_string(s: &str) -> String {
+fn normalize_integer(s: &str) -> String {
+    match split_integer(s).expect("caller is responsible for only normalizing valid integers") {
+        (_, 10, _) => s.to_string(),
+        (negative, radix, digits) => {
+            let magnitude = u128::from_str_radix(digits, radix)
+                .expect("caller is responsible for only normalizing valid integers");
+            if negative && magnitude != 0 {
+                format!("-{}", magnitude)
+            } else {
+                magnitude.to_string()
+            }
+        }
+    }
+}
+
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|b| [nibble_to_hex(b >> 4), nibble_to_hex(b & 0x0f)])
+        .collect()
+}
+
+fn decode_hex(s: &str) -> Vec<u8> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = str::from_utf8(pair).expect("hex digits are ASCII");
+            u8::from_str_radix(pair, 16).expect("caller is responsible for only decoding valid hex")
+        })
+        .collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE64URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn encode_base64(bytes: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | u32::from(*b) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            encoded.push(char::from(
+                alphabet[(group >> (18 - 6 * i) & 0x3f) as usize],
+            ));
+        }
+        if pad {
+            for _ in chunk.len()..3 {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub fn escape_string(s: &str) -> String {
     s.chars()
         .flat_map(|c| match c {
             '\\' | '"' => vec!['\\', c],
@@ -239,73 +505,75 @@ fn escape_string(s: &str) -> String {
 }
 
 fn unescape_string(s: &str) -> String {
-    #[derive(Clone, Copy)]
-    enum State {
-        Normal,
-        Escaped,
-        Hexcode0,
-        Hexcode1,
-        Hexcode2,
-        Hexcode3,
-    }
-    impl State {
-        fn next(self) -> Self {
-            match self {
-                State::Normal => State::Escaped,
-                State::Escaped => State::Hexcode0,
-                State::Hexcode0 => State::Hexcode1,
-                State::Hexcode1 => State::Hexcode2,
-                State::Hexcode2 => State::Hexcode3,
-                State::Hexcode3 => State::Normal,
-            }
-        }
-    }
-    let mut state = State::Normal;
-    let mut acc = 0u32;
-    let unescaped = s.chars().flat_map(|c| match (state, c) {
-        (State::Normal, '\\') => {
-            state = State::Escaped;
-            vec![]
-        }
-        (State::Normal, _)
-        | (State::Escaped, '"')
-        | (State::Escaped, '\\')
-        | (State::Escaped, '/') => {
-            state = State::Normal;
-            vec![c]
-        }
-        (State::Escaped, 'b') => vec![char::from_u32(0x08).expect("valid codepoint")],
-        (State::Escaped, 'f') => vec![char::from_u32(0x0c).expect("valid codepoint")],
-        (State::Escaped, 'n') => vec![char::from_u32(0x0a).expect("valid codepoint")],
-        (State::Escaped, 'r') => vec![char::from_u32(0x0d).expect("valid codepoint")],
-        (State::Escaped, 't') => vec![char::from_u32(0x09).expect("valid codepoint")],
-        (State::Escaped, 'u') => {
-            state = State::Hexcode0;
-            vec![]
-        }
-        (State::Hexcode0 | State::Hexcode1 | State::Hexcode2, _) => {
-            state = state.next();
-            acc = acc << 4
+    format!(r#""{}""#, decode_string(s))
+}
+
+/// Decodes the escape sequences in the text of a JSON string, given without its quotes.
+///
+/// The text must be valid, as it is after parsing a directive or a JSON document.
+pub fn decode_string(s: &str) -> String {
+    fn hexcode(chars: &mut std::str::Chars) -> u32 {
+        chars.by_ref().take(4).fold(0, |acc, c| {
+            acc << 4
                 | c.to_digit(16)
-                    .expect("caller is responsible for only unescaping valid strings");
-            vec![]
-        }
-        (State::Hexcode3, _) => {
-            state = State::Normal;
-            let unescaped = char::from_u32(
-                acc << 4
-                    | c.to_digit(16)
-                        .expect("caller is responsible for only unescaping valid strings"),
-            )
-            .expect("caller is responsible for only unescaping valid strings");
-            acc = 0;
-            vec![unescaped]
+                    .expect("caller is responsible for only unescaping valid strings")
+        })
+    }
+
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
         }
-        _ => unreachable!(),
-    });
-    Some('"')
-        .into_iter()
-        .chain(unescaped)
-        .chain(Some('"').into_iter())
-        .collect()
+        let c = match chars
+            .next()
+            .expect("caller is responsible for only unescaping valid strings")
+        {
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let mut code = hexcode(&mut chars);
+                if (0xd800..0xdc00).contains(&code)
+                    && let Some(low) = chars.as_str().strip_prefix("\\u")
+                    && let low = hexcode(&mut low.chars())
+                    && (0xdc00..0xe000).contains(&low)
+                {
+                    chars.nth(5);
+                    code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                }
+                char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+            c => c,
+        };
+        unescaped.push(c);
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_escaped_surrogate_pairs() {
+        assert_eq!(decode_string(r"\ud83d\ude00"), "\u{1f600}");
+        assert_eq!(decode_string(r"\ud800\ud800\udc00"), "\u{fffd}\u{10000}");
+    }
+
+    #[test]
+    fn replace_lone_high_surrogate() {
+        assert_eq!(decode_string(r"\ud800"), "\u{fffd}");
+        assert_eq!(decode_string(r"\ud800x\n"), "\u{fffd}x\n");
+    }
+
+    #[test]
+    fn replace_high_surrogate_followed_by_non_surrogate() {
+        assert_eq!(decode_string(r"\ud800\u0041"), "\u{fffd}A");
+        assert_eq!(decode_string(r"\udbff\ue000"), "\u{fffd}\u{e000}");
+    }
 }
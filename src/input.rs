@@ -0,0 +1,480 @@
+use crate::parser::is_variable_name;
+use snafu::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// A single directive along with where it came from.
+#[derive(Clone, Debug)]
+pub struct Input {
+    pub bytes: Vec<u8>,
+    pub location: Option<Location>,
+}
+
+impl From<Vec<u8>> for Input {
+    fn from(bytes: Vec<u8>) -> Self {
+        Input {
+            bytes,
+            location: None,
+        }
+    }
+}
+
+/// The position of a directive in a directive file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Location {
+    pub file: Rc<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// Returns the location of the character at position `pos` of a directive starting at this
+    /// location.
+    pub fn advance(&self, text: &str, pos: usize) -> Location {
+        let mut location = self.clone();
+        for c in text.chars().take(pos.saturating_sub(1)) {
+            if c == '\n' {
+                location.line += 1;
+                location.column = 1;
+            } else {
+                location.column += 1;
+            }
+        }
+        location
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum InputError {
+    #[snafu(display("reading \"{file}\": {source}"))]
+    ReadDirectiveFile {
+        source: std::io::Error,
+        file: String,
+    },
+
+    #[snafu(display("{location}: directive file \"{file}\" includes itself"))]
+    RecursiveInclude { location: Location, file: String },
+
+    #[snafu(display("{location}: line continuation at end of file"))]
+    UnterminatedContinuation { location: Location },
+
+    #[snafu(display("{location}: expected variable definition NAME=VALUE"))]
+    InvalidVariableDefinition { location: Location },
+}
+
+type InputResult<T> = Result<T, InputError>;
+
+/// Expands `@file` arguments into the directives of the named directive files.
+///
+/// Other arguments are passed through as they are.
+pub fn expand_args(args: impl Iterator<Item = Vec<u8>>) -> InputResult<Vec<Input>> {
+    let mut inputs = vec![];
+    for arg in args {
+        match arg.strip_prefix(b"@") {
+            Some(file) => read_directive_file(
+                &String::from_utf8_lossy(file),
+                None,
+                &mut vec![],
+                &mut inputs,
+            )?,
+            None => inputs.push(arg.into()),
+        }
+    }
+    Ok(inputs)
+}
+
+/// The argument that separates the directives of one document from those of the next.
+pub const DOCUMENT_SEPARATOR: &[u8] = b",,";
+
+/// Splits directives into groups, one per document, at each [`DOCUMENT_SEPARATOR`].
+///
+/// The separator may be given as an argument or as a line of a directive file.
+/// There is always at least one group, and groups may be empty.
+pub fn split_documents(inputs: Vec<Input>) -> Vec<Vec<Input>> {
+    let mut groups = vec![vec![]];
+    for input in inputs {
+        if input.bytes == DOCUMENT_SEPARATOR {
+            groups.push(vec![]);
+        } else {
+            groups
+                .last_mut()
+                .expect("groups are never empty")
+                .push(input);
+        }
+    }
+    groups
+}
+
+/// Splits NUL-separated directives, as produced by e.g. `find -print0`.
+///
+/// A trailing NUL byte terminates the last directive rather than separating it from an empty
+/// one.
+pub fn split_nul_separated(bytes: &[u8]) -> Vec<Input> {
+    if bytes.is_empty() {
+        return vec![];
+    }
+    let bytes = bytes.strip_suffix(b"\0").unwrap_or(bytes);
+    bytes
+        .split(|b| *b == b'\0')
+        .map(|directive| directive.to_vec().into())
+        .collect()
+}
+
+/// Reads the directives of a directive file.
+///
+/// Directive files contain one directive per line.
+/// Blank lines and lines starting with `#` are ignored, and leading whitespace is removed.
+/// A line ending with a backslash continues on the next line, with the line break kept as part
+/// of the directive.
+/// A line starting with `@` includes the directives of the named directive file, where a
+/// relative file name is relative to the directory of the including file.
+pub fn read_directive_file(
+    file: &str,
+    included_from: Option<&Location>,
+    stack: &mut Vec<PathBuf>,
+    inputs: &mut Vec<Input>,
+) -> InputResult<()> {
+    let canonical = std::fs::canonicalize(file).context(ReadDirectiveFileSnafu { file })?;
+    if stack.contains(&canonical) {
+        RecursiveIncludeSnafu {
+            location: included_from
+                .expect("only included files can be recursive")
+                .clone(),
+            file,
+        }
+        .fail()?;
+    }
+    let content = std::fs::read(file).context(ReadDirectiveFileSnafu { file })?;
+    let name = Rc::new(file.to_string());
+
+    stack.push(canonical);
+    let mut pending: Option<Input> = None;
+    let lines = content.strip_suffix(b"\n").unwrap_or(&content);
+    for (i, line) in lines.split(|b| *b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let (line, continued) = match line.strip_suffix(b"\\") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if let Some(input) = pending.as_mut() {
+            input.bytes.push(b'\n');
+            input.bytes.extend_from_slice(line);
+        } else {
+            let indent = line
+                .iter()
+                .take_while(|b| matches!(b, b' ' | b'\t'))
+                .count();
+            let location = Location {
+                file: name.clone(),
+                line: i + 1,
+                column: indent + 1,
+            };
+            let line = &line[indent..];
+            if (line.is_empty() && !continued) || line.starts_with(b"#") {
+                continue;
+            } else if let Some(included) = line.strip_prefix(b"@").filter(|_| !continued) {
+                let included = Path::new(file)
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .join(&*String::from_utf8_lossy(included));
+                read_directive_file(&included.to_string_lossy(), Some(&location), stack, inputs)?;
+                continue;
+            }
+            pending = Some(Input {
+                bytes: line.to_vec(),
+                location: Some(location),
+            });
+        }
+        if !continued {
+            inputs.extend(pending.take());
+        }
+    }
+    stack.pop();
+
+    if let Some(input) = pending {
+        UnterminatedContinuationSnafu {
+            location: input.location.expect("directive files have locations"),
+        }
+        .fail()?;
+    }
+    Ok(())
+}
+
+/// Splits a variable definition of the form `NAME=VALUE` into its name and value.
+pub fn parse_variable_definition(text: &str) -> Option<(String, String)> {
+    let (name, value) = text.split_once('=')?;
+    if !is_variable_name(name) {
+        return None;
+    }
+    Some((name.to_string(), value.to_string()))
+}
+
+/// Reads the variable definitions of a variables file.
+///
+/// Variables files contain one `NAME=VALUE` definition per line.
+/// Blank lines and lines starting with `#` are ignored, and leading whitespace is removed.
+/// The value is the rest of the line, taken as it is.
+pub fn read_vars_file(file: &str, variables: &mut HashMap<String, String>) -> InputResult<()> {
+    let content = std::fs::read_to_string(file).context(ReadDirectiveFileSnafu { file })?;
+    let name = Rc::new(file.to_string());
+    for (i, line) in content.lines().enumerate() {
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        let line = &line[indent..];
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (variable, value) =
+            parse_variable_definition(line).context(InvalidVariableDefinitionSnafu {
+                location: Location {
+                    file: name.clone(),
+                    line: i + 1,
+                    column: indent + 1,
+                },
+            })?;
+        variables.insert(variable, value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use assert_matches::assert_matches;
+
+    fn read(file: &str) -> InputResult<Vec<(String, usize, usize)>> {
+        let mut inputs = vec![];
+        read_directive_file(file, None, &mut vec![], &mut inputs)?;
+        Ok(inputs
+            .into_iter()
+            .map(|input| {
+                let location = input.location.unwrap();
+                (
+                    String::from_utf8(input.bytes).unwrap(),
+                    location.line,
+                    location.column,
+                )
+            })
+            .collect())
+    }
+
+    #[test]
+    fn read_one_directive_per_line() {
+        let dir = TempDir::new("lines");
+        let file = dir.file("lines", "a=1\r\nb:2\n\n  c=3 \n");
+        assert_eq!(
+            read(&file).unwrap(),
+            [
+                ("a=1".to_string(), 1, 1),
+                ("b:2".to_string(), 2, 1),
+                ("c=3 ".to_string(), 4, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_comments() {
+        let dir = TempDir::new("comments");
+        let file = dir.file("comments", "# a=1\n  # b=2\nc=# not a comment\n");
+        assert_eq!(
+            read(&file).unwrap(),
+            [("c=# not a comment".to_string(), 3, 1)]
+        );
+    }
+
+    #[test]
+    fn keep_line_breaks_of_continued_lines() {
+        let dir = TempDir::new("continuation");
+        let file = dir.file("continuation", "a=first\\\n  second\\\n\nb=x\n");
+        assert_eq!(
+            read(&file).unwrap(),
+            [
+                ("a=first\n  second\n".to_string(), 1, 1),
+                ("b=x".to_string(), 4, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_continuation_at_end_of_file() {
+        let dir = TempDir::new("unterminated");
+        let file = dir.file("unterminated", "a=1\nb=2\\");
+        assert_matches!(
+            read(&file),
+            Err(InputError::UnterminatedContinuation { location }) if location.line == 2
+        );
+    }
+
+    #[test]
+    fn include_directive_files() {
+        let dir = TempDir::new("include");
+        let inner = dir.file("inner", "b=2\n");
+        let outer = dir.file("outer", format!("a=1\n@{inner}\nc=3\n"));
+        assert_eq!(
+            read(&outer).unwrap(),
+            [
+                ("a=1".to_string(), 1, 1),
+                ("b=2".to_string(), 1, 1),
+                ("c=3".to_string(), 3, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn include_files_relative_to_including_file() {
+        let dir = TempDir::new("relative");
+        dir.file("sub/inner", "b=2\n");
+        dir.file("sub/middle", "@inner\n");
+        let outer = dir.file("outer", "a=1\n@sub/middle\n");
+        let mut inputs = vec![];
+        read_directive_file(&outer, None, &mut vec![], &mut inputs).unwrap();
+        let locations: Vec<_> = inputs
+            .into_iter()
+            .map(|input| input.location.unwrap().file.to_string())
+            .collect();
+        assert_eq!(
+            locations,
+            [
+                outer.clone(),
+                dir.0.join("sub/inner").to_str().unwrap().to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_recursive_includes() {
+        let dir = TempDir::new("loop");
+        let file = dir.file("loop", "a=1\n@loop\n");
+        assert_matches!(
+            read(&file),
+            Err(InputError::RecursiveInclude { location, .. }) if location.line == 2
+        );
+    }
+
+    #[test]
+    fn read_variable_definitions() {
+        let dir = TempDir::new("vars");
+        let file = dir.file(
+            "vars",
+            "# hosts\nhost=example.com\r\n\n  path=/a=b \nhost=example.org\n",
+        );
+        let mut variables = HashMap::new();
+        read_vars_file(&file, &mut variables).unwrap();
+        assert_eq!(
+            variables,
+            HashMap::from([
+                ("host".to_string(), "example.org".to_string()),
+                ("path".to_string(), "/a=b ".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn reject_invalid_variable_definitions() {
+        let dir = TempDir::new("bad-vars");
+        let file = dir.file("bad-vars", "a=1\n  b-c=2\n");
+        assert_matches!(
+            read_vars_file(&file, &mut HashMap::new()),
+            Err(InputError::InvalidVariableDefinition { location })
+            if location.line == 2 && location.column == 3
+        );
+        assert_eq!(parse_variable_definition("=x"), None);
+        assert_eq!(parse_variable_definition("x"), None);
+    }
+
+    #[test]
+    fn split_nul_separated_directives() {
+        let split = |bytes: &[u8]| -> Vec<Vec<u8>> {
+            split_nul_separated(bytes)
+                .into_iter()
+                .map(|input| input.bytes)
+                .collect()
+        };
+        assert_eq!(split(b""), Vec::<Vec<u8>>::new());
+        assert_eq!(split(b"a=1"), [b"a=1".to_vec()]);
+        assert_eq!(split(b"a=1\0"), [b"a=1".to_vec()]);
+        assert_eq!(split(b"a=1\0b=\n\0"), [b"a=1".to_vec(), b"b=\n".to_vec()]);
+        assert_eq!(split(b"a=1\0\0"), [b"a=1".to_vec(), b"".to_vec()]);
+        assert_eq!(split(b"\0"), [b"".to_vec()]);
+    }
+
+    #[test]
+    fn split_arguments_into_documents() {
+        let split = |args: &[&str]| -> Vec<Vec<Vec<u8>>> {
+            let inputs = args
+                .iter()
+                .map(|arg| arg.as_bytes().to_vec().into())
+                .collect();
+            split_documents(inputs)
+                .into_iter()
+                .map(|group| group.into_iter().map(|input| input.bytes).collect())
+                .collect()
+        };
+        assert_eq!(split(&[]), [Vec::<Vec<u8>>::new()]);
+        assert_eq!(
+            split(&["a=1", "b=,,"]),
+            [vec![b"a=1".to_vec(), b"b=,,".to_vec()]]
+        );
+        assert_eq!(
+            split(&["a=1", ",,", "b=2", "c=3"]),
+            [
+                vec![b"a=1".to_vec()],
+                vec![b"b=2".to_vec(), b"c=3".to_vec()]
+            ]
+        );
+        assert_eq!(split(&[",,", ",,"]), vec![Vec::<Vec<u8>>::new(); 3]);
+    }
+
+    #[test]
+    fn split_directive_files_into_documents() {
+        let dir = TempDir::new("documents");
+        let file = dir.file("documents", "a=1\n  ,,\nb=2\nc=,,\\\n,,\n");
+        let inputs = expand_args([format!("@{file}").into_bytes(), b"d=3".to_vec()].into_iter());
+        let groups: Vec<Vec<_>> = split_documents(inputs.unwrap())
+            .into_iter()
+            .map(|group| group.into_iter().map(|input| input.bytes).collect())
+            .collect();
+        assert_eq!(
+            groups,
+            [
+                vec![b"a=1".to_vec()],
+                vec![b"b=2".to_vec(), b"c=,,\n,,".to_vec(), b"d=3".to_vec()]
+            ]
+        );
+    }
+
+    #[test]
+    fn expand_only_arguments_starting_with_at_sign() {
+        let dir = TempDir::new("args");
+        let file = dir.file("args", "b=2\n");
+        let inputs = expand_args(
+            [
+                b"a=1".to_vec(),
+                format!("@{file}").into_bytes(),
+                b"c@=x".to_vec(),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        let inputs: Vec<_> = inputs
+            .into_iter()
+            .map(|input| (input.bytes, input.location.is_some()))
+            .collect();
+        assert_eq!(
+            inputs,
+            [
+                (b"a=1".to_vec(), false),
+                (b"b=2".to_vec(), true),
+                (b"c@=x".to_vec(), false),
+            ]
+        );
+    }
+}
@@ -1,5 +1,14 @@
+pub mod binary;
 pub mod composer;
+pub mod diff;
 pub mod directive;
+pub mod input;
 pub mod node;
+pub mod output;
 pub mod parser;
+pub mod records;
+#[cfg(test)]
+mod testing;
+pub mod toml;
 pub mod validator;
+pub mod yaml;
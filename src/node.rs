@@ -1,6 +1,16 @@
 use crate::directive::Directive;
 use crate::directive::Path;
 use crate::directive::Segment;
+use crate::parser::DirectiveAst;
+use crate::parser::OperatorAst;
+use crate::parser::SourceAst;
+use crate::parser::SyntaxError;
+use crate::parser::validate_json;
+use crate::validator::NodeKind;
+use crate::validator::PathError;
+use crate::validator::PathErrorVariant;
+use crate::validator::validate;
+use snafu::prelude::*;
 use std::collections::BTreeMap;
 use std::collections::btree_map::Entry;
 use std::rc::Rc;
@@ -17,11 +27,80 @@ pub fn build_tree(mut directives: impl Iterator<Item = Directive>) -> Option<Nod
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Node {
     Value(String),
     Array(BTreeMap<u32, Node>),
-    Object(BTreeMap<Rc<String>, Node>),
+    Object(Members),
+}
+
+/// The members of an object.
+///
+/// Members can be iterated over both sorted by key and in the order they were inserted.
+#[derive(Clone, Debug, Default)]
+pub struct Members {
+    map: BTreeMap<Rc<String>, Node>,
+    order: Vec<Rc<String>>,
+}
+
+impl Members {
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn get(&self, key: &Rc<String>) -> Option<&Node> {
+        self.map.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &Rc<String>) -> Option<&mut Node> {
+        self.map.get_mut(key)
+    }
+
+    /// Inserts a member, keeping the position of any member it replaces.
+    pub fn insert(&mut self, key: Rc<String>, node: Node) -> Option<Node> {
+        let replaced = self.map.insert(key.clone(), node);
+        if replaced.is_none() {
+            self.order.push(key);
+        }
+        replaced
+    }
+
+    pub fn remove(&mut self, key: &Rc<String>) -> Option<Node> {
+        let removed = self.map.remove(key)?;
+        self.order.retain(|k| k != key);
+        Some(removed)
+    }
+
+    pub fn get_or_insert_with(&mut self, key: Rc<String>, f: impl FnOnce() -> Node) -> &mut Node {
+        if !self.map.contains_key(&key) {
+            self.order.push(key.clone());
+        }
+        self.map.entry(key).or_insert_with(f)
+    }
+
+    /// Iterates over the members sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&Rc<String>, &Node)> {
+        self.map.iter()
+    }
+
+    /// Iterates over the members in the order they were inserted.
+    pub fn iter_ordered(&self) -> impl Iterator<Item = (&Rc<String>, &Node)> {
+        self.order.iter().map(|key| (key, &self.map[key]))
+    }
+}
+
+impl FromIterator<(Rc<String>, Node)> for Members {
+    fn from_iter<T: IntoIterator<Item = (Rc<String>, Node)>>(iter: T) -> Self {
+        let mut members = Members::default();
+        for (key, node) in iter {
+            members.insert(key, node);
+        }
+        members
+    }
 }
 
 impl Node {
@@ -32,7 +111,7 @@ impl Node {
                 let child = Node::create(&rest, value);
                 match first {
                     Segment::Index(index) => Node::Array(BTreeMap::from([(index, child)])),
-                    Segment::Key(key) => Node::Object(BTreeMap::from([(key, child)])),
+                    Segment::Key(key) => Node::Object(Members::from_iter([(key, child)])),
                 }
             }
         }
@@ -59,18 +138,246 @@ impl Node {
                 let Node::Object(object) = self else {
                     return false;
                 };
-                match object.entry(key) {
-                    Entry::Vacant(vacant) => {
-                        vacant.insert(Node::create(&rest, value));
+                match object.get_mut(&key) {
+                    Some(member) => member.insert(&rest, value),
+                    None => {
+                        object.insert(key, Node::create(&rest, value));
                         true
                     }
-                    Entry::Occupied(mut occupied) => occupied.get_mut().insert(&rest, value),
                 }
             }
         }
     }
 }
 
+impl Node {
+    /// The kind of the node, where empty objects and arrays may also be held as values.
+    pub fn kind(&self) -> NodeKind {
+        match self {
+            Node::Array(_) => NodeKind::Array,
+            Node::Object(_) => NodeKind::Object,
+            Node::Value(value) if value == "[]" => NodeKind::Array,
+            Node::Value(value) if value == "{}" => NodeKind::Object,
+            Node::Value(_) => NodeKind::Value,
+        }
+    }
+
+    pub fn get(&self, path: &Rc<Path>) -> Option<&Node> {
+        let Some((first, rest)) = path.split_first() else {
+            return Some(self);
+        };
+        let child = match (self, first) {
+            (Node::Array(array), Segment::Index(index)) => array.get(&index)?,
+            (Node::Object(object), Segment::Key(key)) => object.get(&key)?,
+            _ => return None,
+        };
+        child.get(&rest)
+    }
+
+    /// Removes the node at a path below this one.
+    ///
+    /// Removing an element of an array moves the elements after it down by one index.
+    pub fn remove(&mut self, path: &Rc<Path>) -> Option<Node> {
+        let (first, rest) = path
+            .split_first()
+            .expect("caller is responsible for root paths");
+        match (self, first) {
+            (Node::Array(array), Segment::Index(index)) if rest.is_empty() => {
+                let removed = array.remove(&index)?;
+                let tail = array.split_off(&index);
+                array.extend(tail.into_iter().map(|(i, node)| (i - 1, node)));
+                Some(removed)
+            }
+            (Node::Object(object), Segment::Key(key)) if rest.is_empty() => object.remove(&key),
+            (Node::Array(array), Segment::Index(index)) => array.get_mut(&index)?.remove(&rest),
+            (Node::Object(object), Segment::Key(key)) => object.get_mut(&key)?.remove(&rest),
+            _ => None,
+        }
+    }
+
+    /// Puts a node at a path below this one, replacing any node already there.
+    ///
+    /// Missing objects and arrays along the path are created, but arrays can only be extended
+    /// by their next index.
+    pub fn replace(&mut self, path: &Rc<Path>, node: Node) -> Result<(), PathError> {
+        let mut segments: Vec<_> = path.iter().map(|(_, segment)| segment).collect();
+        segments.reverse();
+        let (last, segments) = segments
+            .split_last()
+            .expect("caller is responsible for root paths");
+
+        let mut parent = self;
+        let mut prefix = Path::root();
+        for (i, segment) in segments.iter().chain([last]).enumerate() {
+            if parent.is_empty_value() {
+                *parent = match parent.kind() {
+                    NodeKind::Array => Node::Array(BTreeMap::new()),
+                    _ => Node::Object(Members::default()),
+                };
+            }
+            let is_last = i == segments.len();
+            let child = || match segments.get(i + 1).unwrap_or(last) {
+                Segment::Index(_) => Node::Array(BTreeMap::new()),
+                Segment::Key(_) => Node::Object(Members::default()),
+            };
+            parent = match (parent, segment) {
+                (Node::Array(array), Segment::Index(index)) => {
+                    let length = array.len() as u32;
+                    if *index > length {
+                        Err(PathError {
+                            path: prefix.clone(),
+                            variant: PathErrorVariant::IncompleteArray {
+                                index_seen: *index,
+                                index_missing: length,
+                            },
+                        })?;
+                    }
+                    if is_last {
+                        array.insert(*index, node);
+                        return Ok(());
+                    }
+                    array.entry(*index).or_insert_with(child)
+                }
+                (Node::Object(object), Segment::Key(key)) => {
+                    if is_last {
+                        object.insert(key.clone(), node);
+                        return Ok(());
+                    }
+                    object.get_or_insert_with(key.clone(), child)
+                }
+                (parent, segment) => Err(PathError {
+                    path: prefix.clone(),
+                    variant: PathErrorVariant::StructuralConflict {
+                        kind1: parent.kind(),
+                        kind2: segment.kind(),
+                    },
+                })?,
+            };
+            prefix = prefix.append(segment.clone());
+        }
+        unreachable!("the last segment returns")
+    }
+
+    /// Reorders the members of objects to follow the members of the same objects in another
+    /// tree.
+    ///
+    /// Members that the other tree lacks keep their order, after those that it has.
+    pub fn order_like(&mut self, other: &Node) {
+        match (self, other) {
+            (Node::Object(members), Node::Object(others)) => {
+                let mut order: Vec<_> = others
+                    .order
+                    .iter()
+                    .filter(|key| members.map.contains_key(*key))
+                    .cloned()
+                    .collect();
+                order.extend(
+                    members
+                        .order
+                        .iter()
+                        .filter(|key| !others.map.contains_key(*key))
+                        .cloned(),
+                );
+                members.order = order;
+                for (key, member) in members.map.iter_mut() {
+                    if let Some(other) = others.map.get(key) {
+                        member.order_like(other);
+                    }
+                }
+            }
+            (Node::Array(elements), Node::Array(others)) => {
+                for (index, element) in elements.iter_mut() {
+                    if let Some(other) = others.get(index) {
+                        element.order_like(other);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_empty_value(&self) -> bool {
+        matches!(self, Node::Value(value) if value == "[]" || value == "{}")
+    }
+
+    /// Breaks the tree down into one directive per leaf, in the order of the tree.
+    ///
+    /// Empty objects and arrays are leaves, just like in JSON directives.
+    pub fn to_directives(&self) -> Vec<Directive> {
+        self.to_directives_at(Path::root())
+    }
+
+    /// Breaks the tree down like [`Node::to_directives`], for a tree located at `path`.
+    pub fn to_directives_at(&self, path: Rc<Path>) -> Vec<Directive> {
+        fn aux(node: &Node, path: Rc<Path>, directives: &mut Vec<Directive>) {
+            match node {
+                Node::Value(value) => directives.push(Directive {
+                    path,
+                    value: value.clone(),
+                }),
+                Node::Array(array) if array.is_empty() => directives.push(Directive {
+                    path,
+                    value: "[]".to_string(),
+                }),
+                Node::Object(object) if object.is_empty() => directives.push(Directive {
+                    path,
+                    value: "{}".to_string(),
+                }),
+                Node::Array(array) => {
+                    for (index, element) in array {
+                        aux(element, path.append(Segment::Index(*index)), directives);
+                    }
+                }
+                Node::Object(object) => {
+                    for (key, member) in object.iter_ordered() {
+                        aux(member, path.append(Segment::Key(key.clone())), directives);
+                    }
+                }
+            }
+        }
+        let mut directives = vec![];
+        aux(self, path, &mut directives);
+        directives
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum DocumentError {
+    #[snafu(display("{source}"))]
+    InvalidDocument { source: SyntaxError },
+
+    #[snafu(display("path {path}: duplicate key"))]
+    DuplicateKey { path: Rc<Path> },
+}
+
+impl std::str::FromStr for Node {
+    type Err = DocumentError;
+
+    /// Parses a JSON document, keeping the text of its keys and scalar values as it is.
+    ///
+    /// Keys that occur more than once in an object are rejected, just like in JSON values of
+    /// directives.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        validate_json(1, s).context(InvalidDocumentSnafu)?;
+        let directives = Directive::from_ast(DirectiveAst {
+            path: vec![],
+            source: SourceAst::Inline,
+            operator: OperatorAst::Colon,
+            value: s.to_string(),
+        });
+        if let Err(error) = validate(&directives) {
+            let path = match error.source.variant {
+                PathErrorVariant::InconsistentKeyEncodings { encoding2, .. } => {
+                    error.source.path.append(encoding2)
+                }
+                _ => error.source.path,
+            };
+            return DuplicateKeySnafu { path }.fail();
+        }
+        Ok(build_tree(directives.into_iter()).expect("JSON values have at least one leaf"))
+    }
+}
+
 impl std::fmt::Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
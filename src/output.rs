@@ -0,0 +1,606 @@
+use crate::directive::Directive;
+use crate::directive::Path;
+use crate::directive::Segment;
+use crate::directive::decode_string;
+use crate::directive::escape_string;
+use crate::node::Members;
+use crate::node::Node;
+use snafu::prelude::*;
+use std::fmt::Write;
+use std::rc::Rc;
+
+/// How a tree is written as JSON text.
+#[derive(Clone, Debug, Default)]
+pub struct Style {
+    /// Whether object members are written in the order they were inserted rather than sorted
+    /// by key
+    pub preserve_order: bool,
+    /// The indentation of each level of nesting, or `None` to write everything on one line
+    pub indent: Option<String>,
+    /// Whether keys, strings, numbers and literals are colored with ANSI escape sequences
+    pub color: bool,
+    /// Whether all non-ASCII characters of keys and strings are written as `\uXXXX` escapes
+    pub ascii: bool,
+    /// Whether `<`, `>`, `&`, U+2028 and U+2029 in keys and strings are written as `\uXXXX`
+    /// escapes, so that the JSON can be embedded in HTML `<script>` elements
+    pub html_safe: bool,
+}
+
+impl Style {
+    /// Returns a style that keeps the key order and the indentation of a JSON document.
+    ///
+    /// The indentation unit is taken from the first indented line of the document.
+    /// Documents on a single line are written compactly.
+    pub fn of_document(text: &str) -> Style {
+        let indent = if text.trim().contains('\n') {
+            let unit = text
+                .lines()
+                .skip(1)
+                .map(|line| &line[..line.len() - line.trim_start_matches([' ', '\t']).len()])
+                .find(|indent| !indent.is_empty())
+                .unwrap_or_default();
+            Some(unit.to_string())
+        } else {
+            None
+        };
+        Style {
+            preserve_order: true,
+            indent,
+            ..Style::default()
+        }
+    }
+}
+
+pub fn to_json(node: &Node, style: &Style) -> String {
+    let mut out = String::new();
+    write_node(&mut out, node, style, 0);
+    out
+}
+
+#[derive(Debug, Snafu)]
+pub enum CanonicalError {
+    #[snafu(display(
+        "path {path}: number {number} differs from the shortest form of the nearest double"
+    ))]
+    InexactNumber { path: Rc<Path>, number: String },
+
+    #[snafu(display("path {path}: object has the key {key} more than once"))]
+    DuplicateKey { path: Rc<Path>, key: String },
+}
+
+/// Writes a tree as canonical JSON according to the JSON Canonicalization Scheme (RFC 8785).
+///
+/// Strings are written with the minimal escapes, keys are sorted by their UTF-16 code units,
+/// and numbers are written the way ECMAScript writes double-precision numbers.
+/// Numbers are rejected unless the shortest form of the nearest double has the same decimal
+/// value, so e.g. `0.1` is accepted, but `0.30000000000000001` is rejected because it would be
+/// written as `0.3`.
+pub fn to_canonical_json(node: &Node) -> Result<String, CanonicalError> {
+    let mut out = String::new();
+    write_canonical(&mut out, node, &Path::root())?;
+    Ok(out)
+}
+
+fn write_canonical(out: &mut String, node: &Node, path: &Rc<Path>) -> Result<(), CanonicalError> {
+    match node {
+        Node::Value(value) if value.starts_with('"') => {
+            out.push('"');
+            out.push_str(&escape_string(&decode_string(&value[1..value.len() - 1])));
+            out.push('"');
+        }
+        Node::Value(value) if matches!(value.as_str(), "true" | "false" | "null" | "[]" | "{}") => {
+            out.push_str(value)
+        }
+        Node::Value(value) => {
+            let number = canonical_number(value).context(InexactNumberSnafu {
+                path: path.clone(),
+                number: value,
+            })?;
+            out.push_str(&number);
+        }
+        Node::Array(array) => {
+            out.push('[');
+            for (i, (index, element)) in array.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(out, element, &path.append(Segment::Index(*index)))?;
+            }
+            out.push(']');
+        }
+        Node::Object(object) => {
+            let mut members: Vec<_> = object
+                .iter()
+                .map(|(key, member)| {
+                    let string = decode_string(key);
+                    let path = path.append(Segment::Key(key.clone()));
+                    (
+                        string.encode_utf16().collect::<Vec<_>>(),
+                        string,
+                        member,
+                        path,
+                    )
+                })
+                .collect();
+            members.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+            out.push('{');
+            for (i, (_, key, member, path)) in members.iter().enumerate() {
+                if i > 0 {
+                    if members[i - 1].1 == *key {
+                        DuplicateKeySnafu {
+                            path: path.prefix().expect("member paths have a prefix"),
+                            key: format!(r#""{}""#, escape_string(key)),
+                        }
+                        .fail()?;
+                    }
+                    out.push(',');
+                }
+                write!(out, r#""{}":"#, escape_string(key)).expect("writing to a string");
+                write_canonical(out, member, path)?;
+            }
+            out.push('}');
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites a JSON number the way ECMAScript writes the nearest double, i.e. in the shortest
+/// form that round-trips, or returns `None` if that form has a different decimal value than the
+/// number.
+pub(crate) fn canonical_number(value: &str) -> Option<String> {
+    let double: f64 = value
+        .parse()
+        .ok()
+        .filter(|double: &f64| double.is_finite())?;
+    // Rust writes the shortest digits that round-trip, just like ECMAScript does
+    let scientific = format!("{:e}", double.abs());
+    let (mantissa, exponent) = scientific.split_once('e').expect("scientific notation");
+    let digits = mantissa.replace('.', "");
+    let k = digits.len() as i32;
+    let n = exponent.parse::<i32>().expect("integer exponent") + 1;
+    let magnitude = if double == 0.0 {
+        "0".to_string()
+    } else if k <= n && n <= 21 {
+        format!("{}{}", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 21 {
+        format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let sign = if n > 0 { '+' } else { '-' };
+        match digits.split_at(1) {
+            (first, "") => format!("{}e{}{}", first, sign, (n - 1).abs()),
+            (first, rest) => format!("{}.{}e{}{}", first, rest, sign, (n - 1).abs()),
+        }
+    };
+    let canonical = if double < 0.0 {
+        format!("-{}", magnitude)
+    } else {
+        magnitude
+    };
+    (decimal_value(&canonical) == decimal_value(value)).then_some(canonical)
+}
+
+/// Returns the sign, significant digits and exponent of a JSON number, with zero having no
+/// sign and no digits.
+fn decimal_value(number: &str) -> (bool, String, i64) {
+    let (negative, number) = match number.strip_prefix('-') {
+        Some(number) => (true, number),
+        None => (false, number),
+    };
+    let (mantissa, exponent) = number.split_once(['e', 'E']).unwrap_or((number, "0"));
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", integer, fraction);
+    let significant = digits.trim_start_matches('0').trim_end_matches('0');
+    if significant.is_empty() {
+        return (false, String::new(), 0);
+    }
+    let trailing_zeros = digits.len() - digits.trim_end_matches('0').len();
+    let exponent =
+        exponent.parse::<i64>().unwrap_or_default() - fraction.len() as i64 + trailing_zeros as i64;
+    (negative, significant.to_string(), exponent)
+}
+
+/// Breaks a tree down into directives that compose it, one per leaf.
+pub fn explode(node: &Node) -> Vec<String> {
+    node.to_directives()
+        .into_iter()
+        .map(|Directive { path, value }| assignment(&path, &value))
+        .collect()
+}
+
+/// Returns a directive that assigns a JSON value to a path.
+///
+/// Strings are assigned with `=` when that reproduces them exactly, and all other values with
+/// `:`.
+pub fn assignment(path: &Path, value: &str) -> String {
+    let string = serde_json::from_str::<String>(value)
+        .ok()
+        .filter(|string| format!(r#""{}""#, escape_string(string)) == value);
+    match string {
+        Some(string) => format!("{}={}", path, string),
+        None => format!("{}:{}", path, value),
+    }
+}
+
+/// How each output document is delimited in a stream of documents.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Frame {
+    /// Followed by a newline
+    Newline,
+    /// Preceded by a record separator (U+001E) and followed by a newline, as in RFC 7464
+    JsonSeq,
+    /// Written as a netstring, i.e. `<length>:<document>,`
+    Netstring,
+    /// Preceded by its length in bytes as a big-endian 32-bit integer
+    U32Be,
+    /// Preceded by its length in bytes as a little-endian 32-bit integer
+    U32Le,
+    /// Written as it is
+    None,
+}
+
+impl std::str::FromStr for Frame {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newline" => Ok(Frame::Newline),
+            "json-seq" => Ok(Frame::JsonSeq),
+            "netstring" => Ok(Frame::Netstring),
+            "u32be" => Ok(Frame::U32Be),
+            "u32le" => Ok(Frame::U32Le),
+            "none" => Ok(Frame::None),
+            _ => {
+                Err("must be one of newline, json-seq, netstring, u32be, u32le or none".to_string())
+            }
+        }
+    }
+}
+
+/// Writes a document delimited by a frame.
+///
+/// Fails without writing anything if the document is too long for a 32-bit length prefix.
+pub fn write_framed(
+    out: &mut impl std::io::Write,
+    document: &[u8],
+    frame: Frame,
+) -> std::io::Result<()> {
+    let length = || {
+        u32::try_from(document.len()).map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "document of {} bytes is too long for a 32-bit length prefix",
+                    document.len()
+                ),
+            )
+        })
+    };
+    match frame {
+        Frame::Newline => {
+            out.write_all(document)?;
+            out.write_all(b"\n")
+        }
+        Frame::JsonSeq => {
+            out.write_all(b"\x1e")?;
+            out.write_all(document)?;
+            out.write_all(b"\n")
+        }
+        Frame::Netstring => {
+            write!(out, "{}:", document.len())?;
+            out.write_all(document)?;
+            out.write_all(b",")
+        }
+        Frame::U32Be => {
+            out.write_all(&length()?.to_be_bytes())?;
+            out.write_all(document)
+        }
+        Frame::U32Le => {
+            out.write_all(&length()?.to_le_bytes())?;
+            out.write_all(document)
+        }
+        Frame::None => out.write_all(document),
+    }
+}
+
+fn write_node(out: &mut String, node: &Node, style: &Style, depth: usize) {
+    match node {
+        Node::Value(value) => match value.as_bytes()[0] {
+            b'"' => write_colored(out, style, STRING_COLOR, &escape_for_style(value, style)),
+            b't' | b'f' | b'n' => write_colored(out, style, LITERAL_COLOR, value),
+            b'[' | b'{' => out.push_str(value),
+            _ => write_colored(out, style, NUMBER_COLOR, value),
+        },
+        Node::Array(array) if array.is_empty() => out.push_str("[]"),
+        Node::Object(object) if object.is_empty() => out.push_str("{}"),
+        Node::Array(array) => {
+            out.push('[');
+            for (i, element) in array.values().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_newline(out, style, depth + 1);
+                write_node(out, element, style, depth + 1);
+            }
+            write_newline(out, style, depth);
+            out.push(']');
+        }
+        Node::Object(object) => {
+            out.push('{');
+            for (i, (key, member)) in members(object, style).enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_newline(out, style, depth + 1);
+                let key = escape_for_style(&format!(r#""{}""#, key), style);
+                write_colored(out, style, KEY_COLOR, &key);
+                out.push_str(if style.indent.is_some() { ": " } else { ":" });
+                write_node(out, member, style, depth + 1);
+            }
+            write_newline(out, style, depth);
+            out.push('}');
+        }
+    }
+}
+
+/// Iterates over the members of an object in the order that the style asks for.
+pub(crate) fn members<'a>(
+    object: &'a Members,
+    style: &Style,
+) -> Box<dyn Iterator<Item = (&'a Rc<String>, &'a Node)> + 'a> {
+    if style.preserve_order {
+        Box::new(object.iter_ordered())
+    } else {
+        Box::new(object.iter())
+    }
+}
+
+/// Escapes the characters of a JSON string that the style asks to have escaped.
+///
+/// Characters are replaced one by one, as escape sequences only ever consist of ASCII
+/// characters that are never escaped.
+fn escape_for_style(string: &str, style: &Style) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        let html_unsafe = matches!(c, '<' | '>' | '&' | '\u{2028}' | '\u{2029}');
+        if (style.ascii && !c.is_ascii()) || (style.html_safe && html_unsafe) {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                write!(escaped, "\\u{:04x}", unit).expect("writing to a string");
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+const KEY_COLOR: &str = "34;1";
+const STRING_COLOR: &str = "32";
+const NUMBER_COLOR: &str = "36";
+const LITERAL_COLOR: &str = "33";
+
+fn write_colored(out: &mut String, style: &Style, color: &str, text: &str) {
+    if style.color {
+        write!(out, "\x1b[{}m{}\x1b[0m", color, text).expect("writing to a string");
+    } else {
+        out.push_str(text);
+    }
+}
+
+fn write_newline(out: &mut String, style: &Style, depth: usize) {
+    if let Some(indent) = &style.indent {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str(indent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    const DOCUMENT: &str = r#"{"b":[1,{}],"a":{"d":[],"c":null}}"#;
+
+    fn write(document: &str, style: &Style) -> String {
+        to_json(&document.parse().unwrap(), style)
+    }
+
+    #[test]
+    fn write_compact_json_sorted_by_key() {
+        assert_eq!(
+            write(DOCUMENT, &Style::default()),
+            r#"{"a":{"c":null,"d":[]},"b":[1,{}]}"#
+        );
+    }
+
+    #[test]
+    fn write_json_in_insertion_order() {
+        let style = Style {
+            preserve_order: true,
+            indent: None,
+            ..Style::default()
+        };
+        assert_eq!(write(DOCUMENT, &style), DOCUMENT);
+    }
+
+    #[test]
+    fn write_keys_in_order_of_first_directive() {
+        let directives = ["b.y=1", "a:{\"d\":2,\"c\":3}", "b.x=4", "b.y2=5"];
+        let tree =
+            crate::composer::compose(directives.iter().map(|s| s.bytes().collect::<Vec<_>>()))
+                .unwrap()
+                .unwrap();
+        let style = Style {
+            preserve_order: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            to_json(&tree, &style),
+            r#"{"b":{"y":"1","x":"4","y2":"5"},"a":{"d":2,"c":3}}"#
+        );
+    }
+
+    #[test]
+    fn write_indented_json() {
+        let style = Style {
+            preserve_order: false,
+            indent: Some("  ".to_string()),
+            ..Style::default()
+        };
+        assert_eq!(
+            write(DOCUMENT, &style),
+            "{\n  \"a\": {\n    \"c\": null,\n    \"d\": []\n  },\n  \"b\": [\n    1,\n    {}\n  ]\n}"
+        );
+        assert_eq!(write("42", &style), "42");
+    }
+
+    #[test]
+    fn write_colored_json() {
+        let style = Style {
+            color: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            write(r#"{"a":[1.50,"\u0041",true,null]}"#, &style),
+            concat!(
+                "{\x1b[34;1m\"a\"\x1b[0m:[\x1b[36m1.50\x1b[0m,\x1b[32m\"\\u0041\"\x1b[0m,",
+                "\x1b[33mtrue\x1b[0m,\x1b[33mnull\x1b[0m]}"
+            )
+        );
+    }
+
+    #[test]
+    fn write_escaped_json() {
+        let document = r#"{"é<":["\u00e9 😀 &amp; \u2028\u2029","</script>"]}"#;
+        let ascii = Style {
+            ascii: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            write(document, &ascii),
+            r#"{"\u00e9<":["\u00e9 \ud83d\ude00 &amp; \u2028\u2029","</script>"]}"#
+        );
+        let html_safe = Style {
+            html_safe: true,
+            ..Style::default()
+        };
+        assert_eq!(
+            write(document, &html_safe),
+            r#"{"é\u003c":["\u00e9 😀 \u0026amp; \u2028\u2029","\u003c/script\u003e"]}"#
+        );
+        assert_eq!(
+            write("\"\u{2028}\u{2029}\"", &html_safe),
+            r#""\u2028\u2029""#
+        );
+    }
+
+    #[test]
+    fn write_canonical_json() {
+        let canonical = |document: &str| to_canonical_json(&document.parse().unwrap());
+        assert_eq!(
+            canonical(r#"[1e30,4.50,2e-3,0.000001,1e-7,-0,-1.5E+2,100,1e21,123e18,[],{}]"#)
+                .unwrap(),
+            "[1e+30,4.5,0.002,0.000001,1e-7,0,-150,100,1e+21,123000000000000000000,[],{}]"
+        );
+        assert_eq!(
+            canonical(r#"["\u20ac\/\u000F\u2028","\ud83d\ude00\n\u007f"]"#).unwrap(),
+            "[\"€/\\u000f\u{2028}\",\"😀\\n\u{7f}\"]"
+        );
+        assert_eq!(
+            canonical(
+                r#"{"\u20ac":1,"\r":2,"\ufb33":3,"1":4,"\ud83d\ude00":5,"\u0080":6,"\u00f6":7}"#
+            )
+            .unwrap(),
+            "{\"\\r\":2,\"1\":4,\"\u{80}\":6,\"ö\":7,\"€\":1,\"😀\":5,\"\u{fb33}\":3}"
+        );
+    }
+
+    #[test]
+    fn reject_non_canonical_values() {
+        let canonical = |document: &str| to_canonical_json(&document.parse().unwrap());
+        assert_matches!(
+            canonical("[0.1,12345678901234567890]"),
+            Err(CanonicalError::InexactNumber { path, number })
+            if path.to_string() == "1" && number == "12345678901234567890"
+        );
+        assert_matches!(
+            canonical("[1e400]"),
+            Err(CanonicalError::InexactNumber { .. })
+        );
+        assert_matches!(
+            canonical("[0.30000000000000001]"),
+            Err(CanonicalError::InexactNumber { .. })
+        );
+        let mut members = Members::default();
+        members.insert(Rc::new("b".to_string()), Node::Value("1".to_string()));
+        members.insert(Rc::new("\\u0062".to_string()), Node::Value("2".to_string()));
+        let mut document = Members::default();
+        document.insert(Rc::new("a".to_string()), Node::Object(members));
+        assert_matches!(
+            to_canonical_json(&Node::Object(document)),
+            Err(CanonicalError::DuplicateKey { path, key })
+            if path.to_string() == "a" && key == r#""b""#
+        );
+    }
+
+    #[test]
+    fn explode_into_directives() {
+        let node = r#"{"b":[1,"x\ty"],"a b":{"c":"\u00e9","d":{}},"é":"é"}"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            explode(&node),
+            [
+                "b.0:1",
+                "b.1=x\ty",
+                r#""a b".c:"\u00e9""#,
+                r#""a b".d:{}"#,
+                "é=é",
+            ]
+        );
+        assert_eq!(explode(&"\"\"".parse().unwrap()), [".="]);
+    }
+
+    #[test]
+    fn compose_exploded_directives() {
+        let document =
+            r#"{"":[[],{"\"":null}],"a":[1.0e2,true,"\u2028"],"b":"\\ \n","c":"\ud83d\ude00"}"#;
+        let directives = explode(&document.parse().unwrap());
+        let tree = crate::composer::compose(directives.into_iter().map(String::into_bytes));
+        assert_eq!(tree.unwrap().unwrap().to_string(), document);
+    }
+
+    #[test]
+    fn detect_style_of_document() {
+        assert_eq!(Style::of_document(DOCUMENT).indent, None);
+        assert_eq!(Style::of_document("{\"a\":1}\n").indent, None);
+        assert_eq!(
+            Style::of_document("{\n    \"a\": [\n        1\n    ]\n}\n").indent,
+            Some("    ".to_string())
+        );
+        assert_eq!(
+            Style::of_document("{\n\t\"a\": 1\n}").indent,
+            Some("\t".to_string())
+        );
+        assert_eq!(Style::of_document("[\n1\n]").indent, Some("".to_string()));
+    }
+
+    #[test]
+    fn frame_documents() {
+        let framed = |frame: &str| {
+            let mut out = vec![];
+            write_framed(&mut out, b"{\"a\":1}", frame.parse().unwrap()).unwrap();
+            out
+        };
+        assert_eq!(framed("newline"), b"{\"a\":1}\n");
+        assert_eq!(framed("json-seq"), b"\x1e{\"a\":1}\n");
+        assert_eq!(framed("netstring"), b"7:{\"a\":1},");
+        assert_eq!(framed("u32be"), b"\0\0\0\x07{\"a\":1}");
+        assert_eq!(framed("u32le"), b"\x07\0\0\0{\"a\":1}");
+        assert_eq!(framed("none"), b"{\"a\":1}");
+        assert!("crlf".parse::<Frame>().is_err());
+    }
+}
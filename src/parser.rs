@@ -5,24 +5,78 @@ use snafu::prelude::*;
 use unicode_ident::is_xid_continue;
 use unicode_ident::is_xid_start;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DirectiveAst {
     pub path: Vec<SegmentAst>,
+    pub source: SourceAst,
     pub operator: OperatorAst,
     pub value: String,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug)]
+pub enum StatementAst {
+    Directive(DirectiveAst),
+    OpenGroup(Vec<SegmentAst>),
+    CloseGroup,
+    Delete(Vec<SegmentAst>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SourceAst {
+    Inline,
+    File,
+    Variable,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum OperatorAst {
     Colon,
     EqualSign,
+    Number,
+    Integer,
+    Boolean,
+    Base64,
+    Base64Url,
+    Template,
+    Copy,
+    Move,
 }
 
-#[derive(Debug)]
+impl OperatorAst {
+    /// Whether the operator takes bytes rather than text from file and variable sources.
+    pub fn is_binary(self) -> bool {
+        matches!(self, OperatorAst::Base64 | OperatorAst::Base64Url)
+    }
+
+    /// Whether the operator takes a number or a boolean given as unquoted text.
+    pub fn is_typed(self) -> bool {
+        matches!(
+            self,
+            OperatorAst::Number | OperatorAst::Integer | OperatorAst::Boolean
+        )
+    }
+
+    /// Whether the operator takes the path of a subtree to copy or move rather than a value.
+    pub fn is_edit(self) -> bool {
+        matches!(self, OperatorAst::Copy | OperatorAst::Move)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum SegmentAst {
     ArrayIndex(u32),
+    NextIndex,
+    LastIndex,
     BareKey(String),
     QuotedKey(String),
+    Range(u32, u32),
+    Alternatives(Vec<SegmentAst>),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TemplatePartAst {
+    Literal(String),
+    Variable(String),
 }
 
 #[derive(Debug, Snafu)]
@@ -39,12 +93,30 @@ pub enum SyntaxError {
         source: std::num::ParseIntError,
     },
 
+    #[snafu(display("position {pos}: range {start}..{end} is empty"))]
+    EmptyRange { pos: usize, start: u32, end: u32 },
+
+    #[snafu(display("position {pos}: range expands to too many paths"))]
+    TooManyPaths { pos: usize },
+
     #[snafu(display("position {pos}: invalid key"))]
     InvalidKey {
         pos: usize,
         source: serde_json::Error,
     },
 
+    #[snafu(display("position {pos}: expected number, got '{value}'"))]
+    InvalidNumber { pos: usize, value: String },
+
+    #[snafu(display("position {pos}: expected integer, got '{value}'"))]
+    InvalidInteger { pos: usize, value: String },
+
+    #[snafu(display("position {pos}: expected boolean, got '{value}'"))]
+    InvalidBoolean { pos: usize, value: String },
+
+    #[snafu(display("position {pos}: unknown type '{name}'"))]
+    UnknownType { pos: usize, name: String },
+
     #[snafu(display("position {pos}: invalid json value"))]
     InvalidJsonValue {
         pos: usize, // TODO: remove this once we can have origin-aware JSON parsing errors
@@ -52,23 +124,28 @@ pub enum SyntaxError {
     },
 }
 
-type ParseResult<'a, T> = Result<(T, usize, &'a str), SyntaxError>;
-
-pub fn validate_json(start_pos: usize, input: &str) -> ParseResult<'_, ()> {
-    if (input.starts_with('{') || input.starts_with('['))
-        && !input.starts_with("{}")
-        && !input.starts_with("[]")
-    {
-        if let Some(ch) = input.chars().nth(1) {
-            Err(SyntaxError::UnexpectedChar {
-                pos: start_pos + 1,
-                ch,
-            })?;
-        } else {
-            Err(SyntaxError::UnexpectedEndOfString)?;
+impl SyntaxError {
+    /// Returns the position of the error within the directive, if it is known.
+    pub fn pos(&self) -> Option<usize> {
+        match self {
+            SyntaxError::UnexpectedChar { pos, .. }
+            | SyntaxError::InvalidIndex { pos, .. }
+            | SyntaxError::EmptyRange { pos, .. }
+            | SyntaxError::TooManyPaths { pos }
+            | SyntaxError::InvalidKey { pos, .. }
+            | SyntaxError::InvalidNumber { pos, .. }
+            | SyntaxError::InvalidInteger { pos, .. }
+            | SyntaxError::InvalidBoolean { pos, .. }
+            | SyntaxError::UnknownType { pos, .. }
+            | SyntaxError::InvalidJsonValue { pos, .. } => Some(*pos),
+            SyntaxError::UnexpectedEndOfString => None,
         }
     }
+}
 
+type ParseResult<'a, T> = Result<(T, usize, &'a str), SyntaxError>;
+
+pub fn validate_json(start_pos: usize, input: &str) -> ParseResult<'_, ()> {
     let de = Deserializer::from_str(input);
     let mut stream = de.into_iter::<Value>();
 
@@ -97,17 +174,216 @@ pub fn validate_json(start_pos: usize, input: &str) -> ParseResult<'_, ()> {
     }
 }
 
+pub fn validate_hex(start_pos: usize, input: &str) -> ParseResult<'_, ()> {
+    if let Some((char_index, ch)) = input
+        .chars()
+        .enumerate()
+        .find(|(_, c)| !c.is_ascii_hexdigit())
+    {
+        Err(SyntaxError::UnexpectedChar {
+            pos: start_pos + char_index,
+            ch,
+        })?;
+    }
+    if !input.len().is_multiple_of(2) {
+        Err(SyntaxError::UnexpectedEndOfString)?;
+    }
+    Ok(((), start_pos + input.len(), ""))
+}
+
+fn is_json_number(input: &str) -> bool {
+    fn digits(input: &str) -> (&str, &str) {
+        input.split_at(
+            input
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(input.len()),
+        )
+    }
+    let input = input.strip_prefix('-').unwrap_or(input);
+    let (integer, rest) = digits(input);
+    if integer.is_empty() || (integer.len() > 1 && integer.starts_with('0')) {
+        return false;
+    }
+    let rest = match rest.strip_prefix('.') {
+        Some(rest) => match digits(rest) {
+            ("", _) => return false,
+            (_, rest) => rest,
+        },
+        None => rest,
+    };
+    match rest.strip_prefix(['e', 'E']) {
+        Some(rest) => {
+            let rest = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+            matches!(digits(rest), (exponent, "") if !exponent.is_empty())
+        }
+        None => rest.is_empty(),
+    }
+}
+
+/// Splits an integer into its sign, its radix and its digits.
+///
+/// Besides decimal JSON integers, hexadecimal (`0x`) and octal (`0o`) integers are recognized.
+pub fn split_integer(input: &str) -> Option<(bool, u32, &str)> {
+    let (negative, unsigned) = match input.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, input),
+    };
+    let (radix, digits) = if let Some(digits) = unsigned.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = unsigned.strip_prefix("0o") {
+        (8, digits)
+    } else if unsigned.len() > 1 && unsigned.starts_with('0') {
+        return None;
+    } else {
+        (10, unsigned)
+    };
+    if !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix)) {
+        Some((negative, radix, digits))
+    } else {
+        None
+    }
+}
+
+pub fn validate_number(start_pos: usize, input: &str) -> ParseResult<'_, ()> {
+    if !is_json_number(input) {
+        Err(SyntaxError::InvalidNumber {
+            pos: start_pos,
+            value: input.to_string(),
+        })?;
+    }
+    Ok(((), start_pos + input.len(), ""))
+}
+
+pub fn validate_integer(start_pos: usize, input: &str) -> ParseResult<'_, ()> {
+    match split_integer(input) {
+        Some((_, 10, _)) => {}
+        Some((_, radix, digits)) if u128::from_str_radix(digits, radix).is_ok() => {}
+        _ => Err(SyntaxError::InvalidInteger {
+            pos: start_pos,
+            value: input.to_string(),
+        })?,
+    }
+    Ok(((), start_pos + input.len(), ""))
+}
+
+pub fn validate_boolean(start_pos: usize, input: &str) -> ParseResult<'_, ()> {
+    if input != "true" && input != "false" {
+        Err(SyntaxError::InvalidBoolean {
+            pos: start_pos,
+            value: input.to_string(),
+        })?;
+    }
+    Ok(((), start_pos + input.len(), ""))
+}
+
+/// Validates an inline value for the given operator.
+pub fn validate_value(operator: OperatorAst, start_pos: usize, input: &str) -> ParseResult<'_, ()> {
+    match operator {
+        OperatorAst::Colon => validate_json(start_pos, input),
+        OperatorAst::EqualSign => Ok(((), start_pos + input.chars().count(), "")),
+        OperatorAst::Number => validate_number(start_pos, input),
+        OperatorAst::Integer => validate_integer(start_pos, input),
+        OperatorAst::Boolean => validate_boolean(start_pos, input),
+        OperatorAst::Base64 | OperatorAst::Base64Url => validate_hex(start_pos, input),
+        OperatorAst::Template => {
+            let (_, pos, rest) = parse_template(start_pos, input)?;
+            Ok(((), pos, rest))
+        }
+        OperatorAst::Copy | OperatorAst::Move => {
+            let (_, pos, rest) = parse_complete_path(start_pos, input)?;
+            Ok(((), pos, rest))
+        }
+    }
+}
+
+pub fn is_variable_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits the text of an interpolating string directive into literal text and references to
+/// variables.
+///
+/// `${name}` refers to the variable `name`, and `$${` stands for a literal `${`.
+pub fn parse_template(start_pos: usize, input: &str) -> ParseResult<'_, Vec<TemplatePartAst>> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    let mut pos = start_pos;
+    let mut rest = input;
+    while let Some(ch) = rest.chars().next() {
+        if let Some(tail) = rest.strip_prefix("$${") {
+            literal.push_str("${");
+            (pos, rest) = (pos + 3, tail);
+        } else if let Some(tail) = rest.strip_prefix("${") {
+            let length = tail
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(tail.len());
+            let (name, tail) = tail.split_at(length);
+            pos += 2 + length;
+            match tail.chars().next() {
+                Some('}') if !name.is_empty() => {}
+                Some(ch) => Err(SyntaxError::UnexpectedChar { pos, ch })?,
+                None => Err(SyntaxError::UnexpectedEndOfString)?,
+            }
+            if !literal.is_empty() {
+                parts.push(TemplatePartAst::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(TemplatePartAst::Variable(name.to_string()));
+            (pos, rest) = (pos + 1, &tail[1..]);
+        } else {
+            literal.push(ch);
+            (pos, rest) = (pos + 1, &rest[ch.len_utf8()..]);
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(TemplatePartAst::Literal(literal));
+    }
+    Ok((parts, pos, ""))
+}
+
+/// Parses a directive, the opening or closing of a group of directives, or a deletion.
+///
+/// A group is opened by a path followed by `.{` and closed by a lone `}`.
+/// A deletion is a path preceded by `!`.
+pub fn parse_statement(start_pos: usize, input: &str) -> ParseResult<'_, StatementAst> {
+    if input == "}" {
+        return Ok((StatementAst::CloseGroup, start_pos + 1, ""));
+    }
+    if let Some(rest) = input.strip_prefix('!') {
+        let (path, pos, rest) = parse_complete_path(start_pos + 1, rest)?;
+        return Ok((StatementAst::Delete(path), pos, rest));
+    }
+    let (path, pos, rest) = parse_path(start_pos, input)?;
+    if rest == ".{" && !path.is_empty() {
+        Ok((StatementAst::OpenGroup(path), pos + 2, ""))
+    } else {
+        let (directive, pos, rest) = parse_assignment(path, pos, rest)?;
+        Ok((StatementAst::Directive(directive), pos, rest))
+    }
+}
+
 pub fn parse_directive(start_pos: usize, input: &str) -> ParseResult<'_, DirectiveAst> {
     let (path, pos, input) = parse_path(start_pos, input)?;
+    parse_assignment(path, pos, input)
+}
+
+fn parse_assignment(
+    path: Vec<SegmentAst>,
+    start_pos: usize,
+    input: &str,
+) -> ParseResult<'_, DirectiveAst> {
+    let (source, pos, input) = parse_source(start_pos, input)?;
     let (operator, pos, input) = parse_operator(pos, input)?;
 
-    if operator == OperatorAst::Colon {
-        validate_json(pos, input)?;
+    if source == SourceAst::Inline {
+        validate_value(operator, pos, input)?;
+    } else if source == SourceAst::Variable && input.is_empty() {
+        Err(SyntaxError::UnexpectedEndOfString)?;
     }
 
     Ok((
         DirectiveAst {
             path,
+            source,
             operator,
             value: input.to_string(),
         },
@@ -117,24 +393,62 @@ pub fn parse_directive(start_pos: usize, input: &str) -> ParseResult<'_, Directi
 }
 
 pub fn parse_path(start_pos: usize, input: &str) -> ParseResult<'_, Vec<SegmentAst>> {
-    if input.starts_with('.') {
-        Ok((vec![], start_pos + 1, &input[1..]))
+    if let Some(rest) = input.strip_prefix('.') {
+        Ok((vec![], start_pos + 1, rest))
     } else {
         let mut segments = vec![];
 
         let (first, mut pos, mut input) = parse_segment(start_pos, input)?;
         segments.push(first);
 
-        while input.starts_with('.') {
+        while input.starts_with('.') && input != ".{" {
             let segment;
             (segment, pos, input) = parse_segment(pos + 1, &input[1..])?;
             segments.push(segment);
         }
 
+        if count_paths(&segments) > MAX_PATHS {
+            Err(SyntaxError::TooManyPaths { pos: start_pos })?;
+        }
+
         Ok((segments, pos, input))
     }
 }
 
+/// The largest number of concrete paths that a single path may expand into.
+pub const MAX_PATHS: usize = 65536;
+
+/// Counts the concrete paths that the brace lists and ranges of a path expand into.
+///
+/// The count saturates instead of overflowing, so it can be safely compared against
+/// [MAX_PATHS].
+pub fn count_paths(segments: &[SegmentAst]) -> usize {
+    fn alternatives(segment: &SegmentAst) -> usize {
+        match segment {
+            SegmentAst::Range(start, end) => (end - start) as usize + 1,
+            SegmentAst::Alternatives(items) => items
+                .iter()
+                .map(alternatives)
+                .fold(0, usize::saturating_add),
+            _ => 1,
+        }
+    }
+
+    segments
+        .iter()
+        .map(alternatives)
+        .fold(1, usize::saturating_mul)
+}
+
+/// Parses a path that makes up the entire input.
+pub fn parse_complete_path(start_pos: usize, input: &str) -> ParseResult<'_, Vec<SegmentAst>> {
+    let (path, pos, rest) = parse_path(start_pos, input)?;
+    if let Some(first) = rest.chars().next() {
+        Err(SyntaxError::UnexpectedChar { pos, ch: first })?;
+    }
+    Ok((path, pos, rest))
+}
+
 pub fn parse_segment(start_pos: usize, input: &str) -> ParseResult<'_, SegmentAst> {
     if input.starts_with('"') {
         #[derive(Eq, PartialEq)]
@@ -192,20 +506,51 @@ pub fn parse_segment(start_pos: usize, input: &str) -> ParseResult<'_, SegmentAs
             start_pos + char_index,
             rest,
         ))
-    } else if input.starts_with('0') {
-        Ok((SegmentAst::ArrayIndex(0), start_pos + 1, &input[1..]))
+    } else if let Some(rest) = input.strip_prefix("+0") {
+        Ok((SegmentAst::LastIndex, start_pos + 2, rest))
+    } else if let Some(rest) = input.strip_prefix('+') {
+        Ok((SegmentAst::NextIndex, start_pos + 1, rest))
     } else if input.starts_with(|ch: char| ch.is_ascii_digit()) {
-        let (char_index, split_index) = input
-            .char_indices()
-            .enumerate()
-            .find(|&(_, (_, c))| !c.is_ascii_digit())
-            .map(|(n, (i, _))| (n, i))
-            .unwrap_or_else(|| (input.chars().count(), input.len()));
-        let (index, rest) = input.split_at(split_index);
-        let index = index.parse().context(InvalidIndexSnafu {
-            pos: start_pos + char_index,
-        })?;
-        Ok((SegmentAst::ArrayIndex(index), start_pos + char_index, rest))
+        let (start, pos, rest) = parse_index(start_pos, input)?;
+        if let Some(rest) = rest.strip_prefix("..") {
+            let (end, end_pos, rest) = parse_index(pos + 2, rest)?;
+            if end < start {
+                Err(SyntaxError::EmptyRange {
+                    pos: start_pos,
+                    start,
+                    end,
+                })?;
+            }
+            Ok((SegmentAst::Range(start, end), end_pos, rest))
+        } else {
+            Ok((SegmentAst::ArrayIndex(start), pos, rest))
+        }
+    } else if let Some(mut rest) = input.strip_prefix('{') {
+        let mut alternatives = vec![];
+        let mut pos = start_pos + 1;
+        loop {
+            let alternative;
+            let item_pos = pos;
+            (alternative, pos, rest) = parse_segment(pos, rest)?;
+            match alternative {
+                SegmentAst::NextIndex | SegmentAst::LastIndex | SegmentAst::Alternatives(_) => {
+                    Err(SyntaxError::UnexpectedChar {
+                        pos: item_pos,
+                        ch: input.chars().nth(item_pos - start_pos).expect("parsed"),
+                    })?
+                }
+                _ => alternatives.push(alternative),
+            }
+            if let Some(tail) = rest.strip_prefix(',') {
+                (pos, rest) = (pos + 1, tail);
+            } else if let Some(tail) = rest.strip_prefix('}') {
+                return Ok((SegmentAst::Alternatives(alternatives), pos + 1, tail));
+            } else if let Some(first) = rest.chars().next() {
+                Err(SyntaxError::UnexpectedChar { pos, ch: first })?;
+            } else {
+                Err(SyntaxError::UnexpectedEndOfString)?;
+            }
+        }
     } else if let Some(first) = input.chars().next() {
         Err(SyntaxError::UnexpectedChar {
             pos: start_pos,
@@ -216,11 +561,78 @@ pub fn parse_segment(start_pos: usize, input: &str) -> ParseResult<'_, SegmentAs
     }
 }
 
-pub fn parse_operator(pos: usize, input: &str) -> ParseResult<OperatorAst> {
-    if input.starts_with(':') {
-        Ok((OperatorAst::Colon, pos + 1, &input[1..]))
-    } else if input.starts_with('=') {
-        Ok((OperatorAst::EqualSign, pos + 1, &input[1..]))
+fn parse_index(start_pos: usize, input: &str) -> ParseResult<'_, u32> {
+    if let Some(rest) = input.strip_prefix('0') {
+        return Ok((0, start_pos + 1, rest));
+    }
+    let (char_index, split_index) = input
+        .char_indices()
+        .enumerate()
+        .find(|&(_, (_, c))| !c.is_ascii_digit())
+        .map(|(n, (i, _))| (n, i))
+        .unwrap_or_else(|| (input.chars().count(), input.len()));
+    let (index, rest) = input.split_at(split_index);
+    if index.is_empty() {
+        return match rest.chars().next() {
+            Some(first) => Err(SyntaxError::UnexpectedChar {
+                pos: start_pos,
+                ch: first,
+            }),
+            None => Err(SyntaxError::UnexpectedEndOfString),
+        };
+    }
+    let index = index.parse().context(InvalidIndexSnafu {
+        pos: start_pos + char_index,
+    })?;
+    Ok((index, start_pos + char_index, rest))
+}
+
+pub fn parse_source(pos: usize, input: &str) -> ParseResult<'_, SourceAst> {
+    if let Some(rest) = input.strip_prefix('@') {
+        Ok((SourceAst::File, pos + 1, rest))
+    } else if let Some(rest) = input.strip_prefix('$') {
+        Ok((SourceAst::Variable, pos + 1, rest))
+    } else {
+        Ok((SourceAst::Inline, pos, input))
+    }
+}
+
+pub fn parse_operator(pos: usize, input: &str) -> ParseResult<'_, OperatorAst> {
+    if let Some(rest) = input.strip_prefix(':') {
+        Ok((OperatorAst::Colon, pos + 1, rest))
+    } else if let Some(rest) = input.strip_prefix('=') {
+        Ok((OperatorAst::EqualSign, pos + 1, rest))
+    } else if let Some(rest) = input.strip_prefix("%=") {
+        Ok((OperatorAst::Template, pos + 2, rest))
+    } else if let Some(rest) = input.strip_prefix('#') {
+        let (char_index, split_index) = rest
+            .char_indices()
+            .enumerate()
+            .find(|&(_, (_, c))| !c.is_ascii_alphanumeric())
+            .map(|(n, (i, _))| (n, i))
+            .unwrap_or_else(|| (rest.chars().count(), rest.len()));
+        let (name, rest) = rest.split_at(split_index);
+        let operator = match name {
+            "" => OperatorAst::Number,
+            "int" => OperatorAst::Integer,
+            "bool" => OperatorAst::Boolean,
+            "base64" => OperatorAst::Base64,
+            "base64url" => OperatorAst::Base64Url,
+            "copy" => OperatorAst::Copy,
+            "move" => OperatorAst::Move,
+            _ => Err(SyntaxError::UnknownType {
+                pos: pos + 1,
+                name: name.to_string(),
+            })?,
+        };
+        let pos = pos + 1 + char_index;
+        if let Some(rest) = rest.strip_prefix('=') {
+            Ok((operator, pos + 1, rest))
+        } else if let Some(first) = rest.chars().next() {
+            Err(SyntaxError::UnexpectedChar { pos, ch: first })
+        } else {
+            Err(SyntaxError::UnexpectedEndOfString)
+        }
     } else if let Some(first) = input.chars().next() {
         Err(SyntaxError::UnexpectedChar { pos, ch: first })
     } else {
@@ -0,0 +1,374 @@
+use crate::composer::BuildError;
+use crate::composer::Options;
+use crate::composer::compose_with;
+use crate::composer::reads_stdin;
+use crate::input::Input;
+use crate::node::Node;
+use snafu::prelude::*;
+use std::io::BufRead;
+
+#[derive(Debug, Snafu)]
+pub enum RecordError {
+    #[snafu(display("reading records: {source}"))]
+    ReadRecords { source: std::io::Error },
+
+    #[snafu(display("record {record}: quoted field is not terminated"))]
+    UnterminatedQuote { record: usize },
+
+    #[snafu(display("record {record}: field {field} has text after its closing quote"))]
+    TextAfterQuote { record: usize, field: usize },
+
+    #[snafu(display("record {record}: there is no field $0, as fields are numbered from 1"))]
+    FieldZero { record: usize },
+
+    #[snafu(display("record {record}: field ${field} is missing, the record has only {count}"))]
+    MissingField {
+        record: usize,
+        field: usize,
+        count: usize,
+    },
+
+    #[snafu(display("record {record}: {source}"))]
+    Compose { record: usize, source: BuildError },
+
+    #[snafu(display(
+        "directive \"{directive}\": stdin holds the records, so no value can be read from it"
+    ))]
+    StdinTemplate { directive: String },
+
+    #[snafu(display(
+        "record {record}: directive \"{directive}\": stdin holds the records, so no value can be read from it"
+    ))]
+    StdinValue { record: usize, directive: String },
+}
+
+type RecordResult<T> = Result<T, RecordError>;
+
+/// How the fields of a record are separated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordFormat {
+    /// Comma-separated values, where fields may be quoted as in RFC 4180
+    Csv,
+    /// Tab-separated values, one record per line, without quoting
+    Tsv,
+}
+
+/// Reads records from a reader, one at a time.
+///
+/// Blank lines are skipped, and a trailing carriage return is removed from each line.
+pub struct Records<R> {
+    reader: R,
+    format: RecordFormat,
+    count: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    pub fn new(reader: R, format: RecordFormat) -> Self {
+        Records {
+            reader,
+            format,
+            count: 0,
+        }
+    }
+
+    /// The number of the last record read, i.e. the number of records read so far.
+    pub fn last_record(&self) -> usize {
+        self.count
+    }
+
+    fn read_line(&mut self, line: &mut String) -> RecordResult<bool> {
+        line.clear();
+        let read = self.reader.read_line(line).context(ReadRecordsSnafu)?;
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(read > 0)
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = RecordResult<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        loop {
+            match self.read_line(&mut line) {
+                Ok(true) if line.is_empty() => continue,
+                Ok(true) => break,
+                Ok(false) => return None,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        self.count += 1;
+        if self.format == RecordFormat::Tsv {
+            return Some(Ok(line.split('\t').map(str::to_string).collect()));
+        }
+
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut closed = false;
+        loop {
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                let field = fields.last_mut().expect("fields are never empty");
+                match c {
+                    '"' if quoted && chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' if quoted => {
+                        quoted = false;
+                        closed = true;
+                    }
+                    ',' if !quoted => {
+                        fields.push(String::new());
+                        closed = false;
+                    }
+                    _ if closed => {
+                        return Some(
+                            TextAfterQuoteSnafu {
+                                record: self.count,
+                                field: fields.len(),
+                            }
+                            .fail(),
+                        );
+                    }
+                    '"' if field.is_empty() => quoted = true,
+                    _ => field.push(c),
+                }
+            }
+            if !quoted {
+                return Some(Ok(fields));
+            }
+            // A quoted field continues on the next line
+            fields
+                .last_mut()
+                .expect("fields are never empty")
+                .push('\n');
+            match self.read_line(&mut line) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return Some(UnterminatedQuoteSnafu { record: self.count }.fail());
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
+
+/// Substitutes the fields of a record for `$1`, `$2` and so on in a directive template.
+///
+/// `${1}`, `${2}` and so on may be used to separate the field number from digits that follow it.
+/// `$$` stands for a single `$`, and any other `$` is kept as it is.
+/// On failure, the number of the missing field is returned.
+pub fn substitute(template: &[u8], fields: &[String]) -> Result<Vec<u8>, usize> {
+    fn field<'a>(digits: &[u8], fields: &'a [String]) -> Result<&'a String, usize> {
+        let field: usize = std::str::from_utf8(digits)
+            .expect("digits are ASCII")
+            .parse()
+            .map_err(|_| usize::MAX)?;
+        field
+            .checked_sub(1)
+            .and_then(|index| fields.get(index))
+            .ok_or(field)
+    }
+
+    let mut directive = vec![];
+    let mut rest = template;
+    while let Some(pos) = rest.iter().position(|b| *b == b'$') {
+        directive.extend_from_slice(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+        if let Some(tail) = rest.strip_prefix(b"$") {
+            directive.push(b'$');
+            rest = tail;
+        } else if digits > 0 {
+            directive.extend_from_slice(field(&rest[..digits], fields)?.as_bytes());
+            rest = &rest[digits..];
+        } else if let Some(tail) = rest.strip_prefix(b"{")
+            && let digits = tail.iter().take_while(|b| b.is_ascii_digit()).count()
+            && digits > 0
+            && tail.get(digits) == Some(&b'}')
+        {
+            directive.extend_from_slice(field(&tail[..digits], fields)?.as_bytes());
+            rest = &tail[digits + 1..];
+        } else {
+            directive.push(b'$');
+        }
+    }
+    directive.extend_from_slice(rest);
+    Ok(directive)
+}
+
+/// Checks that no directive template reads its value from stdin, which holds the records.
+///
+/// Templates that only read from stdin once their fields are substituted are rejected by
+/// [`compose_record`].
+pub fn check_templates(templates: &[Input]) -> RecordResult<()> {
+    match templates.iter().find(|template| reads_stdin(template)) {
+        Some(template) => StdinTemplateSnafu {
+            directive: String::from_utf8_lossy(&template.bytes),
+        }
+        .fail(),
+        None => Ok(()),
+    }
+}
+
+/// Composes a document from directive templates for a single record.
+pub fn compose_record(
+    templates: &[Input],
+    fields: &[String],
+    record: usize,
+    options: &Options,
+) -> RecordResult<Option<Node>> {
+    let mut inputs = vec![];
+    for template in templates {
+        let bytes = substitute(&template.bytes, fields).map_err(|field| match field {
+            0 => RecordError::FieldZero { record },
+            _ => RecordError::MissingField {
+                record,
+                field,
+                count: fields.len(),
+            },
+        })?;
+        let input = Input {
+            bytes,
+            location: template.location.clone(),
+        };
+        ensure!(
+            !reads_stdin(&input),
+            StdinValueSnafu {
+                record,
+                directive: String::from_utf8_lossy(&input.bytes),
+            }
+        );
+        inputs.push(input);
+    }
+    compose_with(inputs.into_iter(), options).context(ComposeSnafu { record })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn read(text: &str, format: RecordFormat) -> Vec<RecordResult<Vec<String>>> {
+        Records::new(text.as_bytes(), format).collect()
+    }
+
+    #[test]
+    fn read_csv_records() {
+        let records = read(
+            "a,b\r\n\n\"x,\"\"y\"\"\",\"multi\nline\",\nplain \"quote\"\n",
+            RecordFormat::Csv,
+        );
+        let records: Vec<_> = records.into_iter().map(Result::unwrap).collect();
+        assert_eq!(
+            records,
+            [
+                vec!["a", "b"],
+                vec!["x,\"y\"", "multi\nline", ""],
+                vec!["plain \"quote\""],
+            ]
+        );
+        assert_matches!(
+            read("a\n\"b\n", RecordFormat::Csv).pop(),
+            Some(Err(RecordError::UnterminatedQuote { record: 2 }))
+        );
+        assert_matches!(
+            read("a\nb,\"c\"d\n", RecordFormat::Csv).pop(),
+            Some(Err(RecordError::TextAfterQuote {
+                record: 2,
+                field: 2
+            }))
+        );
+        assert_matches!(
+            read("\"a\" ,b\n", RecordFormat::Csv).pop(),
+            Some(Err(RecordError::TextAfterQuote { record: 1, .. }))
+        );
+    }
+
+    #[test]
+    fn read_tsv_records() {
+        let records = read("a\t\"b\"\t\n\nc", RecordFormat::Tsv);
+        let records: Vec<_> = records.into_iter().map(Result::unwrap).collect();
+        assert_eq!(records, [vec!["a", "\"b\"", ""], vec!["c"]]);
+    }
+
+    #[test]
+    fn substitute_fields() {
+        let fields = ["Alice".to_string(), "30".to_string()];
+        let substitute = |template: &str| {
+            substitute(template.as_bytes(), &fields).map(|bytes| String::from_utf8(bytes).unwrap())
+        };
+        assert_eq!(substitute("name=$1").unwrap(), "name=Alice");
+        assert_eq!(substitute("$1.age#=$2").unwrap(), "Alice.age#=30");
+        assert_eq!(substitute("a=$$1 $ $x $").unwrap(), "a=$1 $ $x $");
+        assert_eq!(substitute("a=${1}0").unwrap(), "a=Alice0");
+        assert_eq!(
+            substitute("a%=$${1} ${x} ${} ${1").unwrap(),
+            "a%=${1} ${x} ${} ${1"
+        );
+        assert_eq!(substitute("a=${3}"), Err(3));
+        assert_eq!(substitute("a=$3"), Err(3));
+        assert_eq!(substitute("a=$0"), Err(0));
+    }
+
+    #[test]
+    fn report_record_numbers() {
+        let templates: Vec<Input> = vec![b"name=$1".to_vec().into(), b"age#=$2".to_vec().into()];
+        let compose = |fields: &[&str]| {
+            let fields: Vec<_> = fields.iter().map(|s| s.to_string()).collect();
+            compose_record(&templates, &fields, 7, &Options::default())
+        };
+        assert_eq!(
+            compose(&["Alice", "30"]).unwrap().unwrap().to_string(),
+            r#"{"age":30,"name":"Alice"}"#
+        );
+        assert_matches!(
+            compose(&["Alice"]),
+            Err(RecordError::MissingField {
+                record: 7,
+                field: 2,
+                count: 1
+            })
+        );
+        assert_matches!(
+            compose(&["Alice", "thirty"]),
+            Err(RecordError::Compose { record: 7, .. })
+        );
+        let templates: Vec<Input> = vec![b"name=$0".to_vec().into()];
+        assert_matches!(
+            compose_record(&templates, &[], 7, &Options::default()),
+            Err(RecordError::FieldZero { record: 7 })
+        );
+    }
+
+    #[test]
+    fn reject_stdin_sources() {
+        let templates = |templates: &[&str]| -> Vec<Input> {
+            templates
+                .iter()
+                .map(|t| t.as_bytes().to_vec().into())
+                .collect()
+        };
+        assert_matches!(
+            check_templates(&templates(&["a=$1", "b@=-"])),
+            Err(RecordError::StdinTemplate { directive }) if directive == "b@=-"
+        );
+        assert_matches!(
+            check_templates(&templates(&["a@:-"])),
+            Err(RecordError::StdinTemplate { .. })
+        );
+        let templates = templates(&["a=$1", "b@=$2"]);
+        check_templates(&templates).unwrap();
+        let fields = ["x".to_string(), "-".to_string()];
+        assert_matches!(
+            compose_record(&templates, &fields, 3, &Options::default()),
+            Err(RecordError::StdinValue { record: 3, directive }) if directive == "b@=-"
+        );
+    }
+}
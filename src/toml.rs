@@ -0,0 +1,332 @@
+use crate::directive::Path;
+use crate::directive::Segment;
+use crate::directive::decode_string;
+use crate::node::Members;
+use crate::node::Node;
+use crate::output::Style;
+use crate::output::members;
+use crate::validator::NodeKind;
+use snafu::prelude::*;
+use std::fmt::Write;
+use std::rc::Rc;
+
+#[derive(Debug, Snafu)]
+pub enum TomlError {
+    #[snafu(display("TOML documents must be tables, not {kind}s"))]
+    RootNotTable { kind: NodeKind },
+
+    #[snafu(display("path {path}: TOML has no null"))]
+    Null { path: Rc<Path> },
+
+    #[snafu(display("path {path}: number {number} is out of range for TOML"))]
+    NumberOutOfRange { path: Rc<Path>, number: String },
+}
+
+type TomlResult<T> = Result<T, TomlError>;
+
+/// Writes a tree as a TOML document.
+///
+/// Objects are written as tables and arrays of objects as arrays of tables, except inside
+/// other arrays where they are written inline.
+/// Numbers are written exactly as in JSON, as long as they are within the range of TOML
+/// integers and floats, i.e. floats must neither overflow nor underflow to zero as doubles.
+/// Of the style, only the key order is taken into account.
+pub fn to_toml(node: &Node, style: &Style) -> TomlResult<String> {
+    let Node::Object(object) = node else {
+        return RootNotTableSnafu {
+            kind: match node {
+                Node::Value(value) if value == "{}" => return Ok(String::new()),
+                Node::Value(value) if value == "[]" => NodeKind::Array,
+                _ => node.kind(),
+            },
+        }
+        .fail();
+    };
+    let mut out = String::new();
+    write_table(&mut out, object, style, &Path::root())?;
+    Ok(out)
+}
+
+/// Writes the key/value pairs of a table, followed by its tables and arrays of tables.
+fn write_table(
+    out: &mut String,
+    object: &Members,
+    style: &Style,
+    path: &Rc<Path>,
+) -> TomlResult<()> {
+    for (key, member) in members(object, style) {
+        if !is_table(member) && !is_array_of_tables(member) {
+            let path = path.append(Segment::Key(key.clone()));
+            write_key(out, key);
+            out.push_str(" = ");
+            write_inline(out, member, style, &path)?;
+            out.push('\n');
+        }
+    }
+    for (key, member) in members(object, style) {
+        let path = path.append(Segment::Key(key.clone()));
+        match member {
+            Node::Object(object) => {
+                write_header(out, &path, "[", "]");
+                write_table(out, object, style, &path)?;
+            }
+            Node::Value(value) if value == "{}" => write_header(out, &path, "[", "]"),
+            Node::Array(array) if is_array_of_tables(member) => {
+                for (index, element) in array {
+                    write_header(out, &path, "[[", "]]");
+                    if let Node::Object(object) = element {
+                        write_table(out, object, style, &path.append(Segment::Index(*index)))?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_header(out: &mut String, path: &Rc<Path>, open: &str, close: &str) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(open);
+    let keys: Vec<_> = path
+        .iter()
+        .filter_map(|(_, segment)| match segment {
+            Segment::Key(key) => Some(key),
+            Segment::Index(_) => None,
+        })
+        .collect();
+    for (i, key) in keys.iter().rev().enumerate() {
+        if i > 0 {
+            out.push('.');
+        }
+        write_key(out, key);
+    }
+    out.push_str(close);
+    out.push('\n');
+}
+
+fn write_inline(out: &mut String, node: &Node, style: &Style, path: &Rc<Path>) -> TomlResult<()> {
+    match node {
+        Node::Value(value) if value == "null" => NullSnafu { path: path.clone() }.fail()?,
+        Node::Value(value) if value.starts_with('"') => write_string(out, value),
+        Node::Value(value) if value_type(node) == ValueType::Integer => {
+            value.parse::<i64>().ok().context(NumberOutOfRangeSnafu {
+                path: path.clone(),
+                number: value,
+            })?;
+            out.push_str(value);
+        }
+        Node::Value(value) if value_type(node) == ValueType::Float => {
+            // A float with nonzero digits must not be read as zero
+            let underflows = |float: &f64| {
+                *float == 0.0
+                    && value
+                        .chars()
+                        .take_while(|c| !matches!(c, 'e' | 'E'))
+                        .any(|c| matches!(c, '1'..='9'))
+            };
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|float| float.is_finite() && !underflows(float))
+                .context(NumberOutOfRangeSnafu {
+                    path: path.clone(),
+                    number: value,
+                })?;
+            out.push_str(value);
+        }
+        Node::Value(value) => out.push_str(value),
+        Node::Array(array) => {
+            out.push('[');
+            for (i, (index, element)) in array.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                write_inline(out, element, style, &path.append(Segment::Index(*index)))?;
+            }
+            out.push(']');
+        }
+        Node::Object(object) => {
+            out.push('{');
+            for (i, (key, member)) in members(object, style).enumerate() {
+                out.push_str(if i > 0 { ", " } else { " " });
+                write_key(out, key);
+                out.push_str(" = ");
+                write_inline(out, member, style, &path.append(Segment::Key(key.clone())))?;
+            }
+            out.push_str(if object.is_empty() { "}" } else { " }" });
+        }
+    }
+    Ok(())
+}
+
+fn is_table(node: &Node) -> bool {
+    matches!(node, Node::Object(_)) || matches!(node, Node::Value(value) if value == "{}")
+}
+
+fn is_array_of_tables(node: &Node) -> bool {
+    matches!(node, Node::Array(array) if !array.is_empty() && array.values().all(is_table))
+}
+
+/// The TOML type of a value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ValueType {
+    String,
+    Boolean,
+    Null,
+    Integer,
+    Float,
+    Array,
+    Table,
+}
+
+fn value_type(node: &Node) -> ValueType {
+    match node {
+        Node::Value(value) => match value.as_bytes()[0] {
+            b'"' => ValueType::String,
+            b't' | b'f' => ValueType::Boolean,
+            b'n' => ValueType::Null,
+            b'[' => ValueType::Array,
+            b'{' => ValueType::Table,
+            _ if value.contains(['.', 'e', 'E']) => ValueType::Float,
+            _ => ValueType::Integer,
+        },
+        Node::Array(_) => ValueType::Array,
+        Node::Object(_) => ValueType::Table,
+    }
+}
+
+fn write_key(out: &mut String, key: &str) {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        out.push_str(key);
+    } else {
+        write_string(out, &format!(r#""{}""#, key));
+    }
+}
+
+/// Rewrites a JSON string as a TOML basic string.
+///
+/// TOML escapes differ from JSON escapes, e.g. TOML has no `\/` and no surrogate pairs, so the
+/// string is unescaped and escaped again, leaving only control characters escaped.
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in decode_string(&value[1..value.len() - 1]).chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\x08' => out.push_str("\\b"),
+            '\x0c' => out.push_str("\\f"),
+            '\x00'..='\x1f' | '\x7f' => {
+                write!(out, "\\u{:04x}", c as u32).expect("writing to a string")
+            }
+            _ => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    fn toml(document: &str) -> TomlResult<String> {
+        to_toml(&document.parse().unwrap(), &Style::default())
+    }
+
+    #[test]
+    fn write_tables_after_values() {
+        let document = r#"{
+            "package": {"name": "mkjson", "version": "0.1.0", "metadata": {}},
+            "edition": 2024,
+            "bin": [{"name": "a", "path": {"x": 1}}, {"name": "b c"}],
+            "matrix": [[1, 2], [{"a": 1.50}]],
+            "features": {"default": [], "z y": ["x\/\ud83d\ude00\u007f"]}
+        }"#;
+        assert_eq!(
+            toml(document).unwrap(),
+            concat!(
+                "edition = 2024\n",
+                "matrix = [[1, 2], [{ a = 1.50 }]]\n",
+                "\n",
+                "[[bin]]\n",
+                "name = \"a\"\n",
+                "\n",
+                "[bin.path]\n",
+                "x = 1\n",
+                "\n",
+                "[[bin]]\n",
+                "name = \"b c\"\n",
+                "\n",
+                "[features]\n",
+                "default = []\n",
+                "\"z y\" = [\"x/😀\\u007f\"]\n",
+                "\n",
+                "[package]\n",
+                "name = \"mkjson\"\n",
+                "version = \"0.1.0\"\n",
+                "\n",
+                "[package.metadata]\n",
+            )
+        );
+        assert_eq!(toml("{}").unwrap(), "");
+    }
+
+    #[test]
+    fn write_arrays_with_mixed_types() {
+        assert_eq!(
+            toml(r#"{"a":[1,1.5,"x",[],{"b":true}],"c":[{},1]}"#).unwrap(),
+            "a = [1, 1.5, \"x\", [], { b = true }]\nc = [{}, 1]\n"
+        );
+    }
+
+    #[test]
+    fn reject_structures_that_toml_cannot_represent() {
+        assert_matches!(
+            toml("[1]"),
+            Err(TomlError::RootNotTable {
+                kind: NodeKind::Array
+            })
+        );
+        assert_matches!(
+            toml("1"),
+            Err(TomlError::RootNotTable {
+                kind: NodeKind::Value
+            })
+        );
+        assert_matches!(
+            toml(r#"{"a":{"b":[null]}}"#),
+            Err(TomlError::Null { path }) if path.to_string() == "a.b.0"
+        );
+        assert_matches!(
+            toml(r#"{"a":9223372036854775808}"#),
+            Err(TomlError::NumberOutOfRange { number, .. }) if number == "9223372036854775808"
+        );
+        assert_matches!(
+            toml(r#"{"a":-1e400}"#),
+            Err(TomlError::NumberOutOfRange { .. })
+        );
+        assert_matches!(
+            toml(r#"{"a":[0.0,1e-400]}"#),
+            Err(TomlError::NumberOutOfRange { path, number })
+            if path.to_string() == "a.1" && number == "1e-400"
+        );
+        assert_eq!(
+            toml(r#"{"a":[0e-400,-0.0E5,4.9e-324]}"#).unwrap(),
+            "a = [0e-400, -0.0E5, 4.9e-324]\n"
+        );
+        assert_eq!(
+            toml(r#"{"a":-9223372036854775808}"#).unwrap(),
+            "a = -9223372036854775808\n"
+        );
+    }
+}
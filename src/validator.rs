@@ -2,7 +2,7 @@ use crate::directive::Directive;
 use crate::directive::Path;
 use crate::directive::Segment;
 use snafu::prelude::*;
-use std::collections::BTreeSet;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::hash_map::Entry;
@@ -31,9 +31,23 @@ pub enum PathErrorVariant {
 
     #[snafu(display("array at path has index {index_seen} but lacks index {index_missing}",))]
     IncompleteArray { index_seen: u32, index_missing: u32 },
+
+    #[snafu(display("array at path has no elements to refer to"))]
+    NoLastIndex,
+
+    #[snafu(display("path does not exist"))]
+    NoSuchPath,
+}
+
+/// A path error along with the index of the directive that caused it.
+#[derive(Debug, Snafu)]
+#[snafu(display("{source}"))]
+pub struct ValidationError {
+    pub directive: usize,
+    pub source: PathError,
 }
 
-type ValidationResult = Result<(), PathError>;
+type ValidationResult = Result<(), ValidationError>;
 
 pub fn validate(directives: &[Directive]) -> ValidationResult {
     check_key_consistency(directives)?;
@@ -44,7 +58,7 @@ pub fn validate(directives: &[Directive]) -> ValidationResult {
 
 fn check_key_consistency(directives: &[Directive]) -> ValidationResult {
     let mut keys: HashMap<Rc<Path>, Segment> = HashMap::new();
-    for directive in directives {
+    for (i, directive) in directives.iter().enumerate() {
         let mut given_path = directive.path.clone();
         let mut normalized_path = given_path.unescape();
 
@@ -62,7 +76,8 @@ fn check_key_consistency(directives: &[Directive]) -> ValidationResult {
                                     encoding1: occupied.get().clone(),
                                     encoding2: given_segment,
                                 },
-                            })?;
+                            })
+                            .context(ValidationSnafu { directive: i })?;
                         }
                     }
                 }
@@ -79,12 +94,13 @@ fn check_key_consistency(directives: &[Directive]) -> ValidationResult {
 fn check_path_uniqueness(directives: &[Directive]) -> ValidationResult {
     let mut paths = HashSet::new();
 
-    for directive in directives {
+    for (i, directive) in directives.iter().enumerate() {
         if !paths.insert(directive.path.clone()) {
             Err(PathError {
                 variant: PathErrorVariant::ConflictingDirectives,
                 path: directive.path.clone(),
-            })?;
+            })
+            .context(ValidationSnafu { directive: i })?;
         }
     }
     Ok(())
@@ -110,7 +126,7 @@ impl std::fmt::Display for NodeKind {
 fn check_node_types(directives: &[Directive]) -> ValidationResult {
     let mut types: HashMap<Rc<Path>, NodeKind> = HashMap::new();
 
-    for directive in directives {
+    for (i, directive) in directives.iter().enumerate() {
         let mut path = directive.path.clone();
 
         match types.entry(path.clone()) {
@@ -121,7 +137,8 @@ fn check_node_types(directives: &[Directive]) -> ValidationResult {
                     kind1: *occupied.get(),
                     kind2: NodeKind::Value,
                 },
-            })?,
+            })
+            .context(ValidationSnafu { directive: i })?,
         };
 
         while let Some((prefix, segment)) = path.split_last() {
@@ -140,7 +157,8 @@ fn check_node_types(directives: &[Directive]) -> ValidationResult {
                         kind1: *occupied.get(),
                         kind2: kind,
                     },
-                })?,
+                })
+                .context(ValidationSnafu { directive: i })?,
             };
 
             path = prefix;
@@ -151,15 +169,20 @@ fn check_node_types(directives: &[Directive]) -> ValidationResult {
 }
 
 fn check_array_completeness(directives: &[Directive]) -> ValidationResult {
-    let mut arrays: HashMap<Rc<Path>, BTreeSet<u32>> = HashMap::new();
+    // The index of the first directive referring to each array element
+    let mut arrays: HashMap<Rc<Path>, BTreeMap<u32, usize>> = HashMap::new();
 
-    for directive in directives {
+    for (i, directive) in directives.iter().enumerate() {
         let mut path = directive.path.clone();
 
         while let Some((ref prefix, segment)) = path.split_last() {
             match segment {
                 Segment::Index(index) => {
-                    arrays.entry(prefix.clone()).or_default().insert(index);
+                    arrays
+                        .entry(prefix.clone())
+                        .or_default()
+                        .entry(index)
+                        .or_insert(i);
                 }
                 Segment::Key(_) => {}
             };
@@ -170,7 +193,7 @@ fn check_array_completeness(directives: &[Directive]) -> ValidationResult {
     for (prefix, indices) in arrays {
         let indices: Vec<_> = indices.into_iter().collect();
 
-        let first = *indices.first().expect("non-empty");
+        let (first, i) = *indices.first().expect("non-empty");
 
         if first != 0 {
             Err(PathError {
@@ -179,11 +202,14 @@ fn check_array_completeness(directives: &[Directive]) -> ValidationResult {
                     index_seen: first,
                     index_missing: 0,
                 },
-            })?;
+            })
+            .context(ValidationSnafu { directive: i })?;
         }
 
         for pair in indices.windows(2) {
-            let [left, right] = pair else { unreachable!() };
+            let [(left, _), (right, i)] = pair else {
+                unreachable!()
+            };
             if *left != right - 1 {
                 Err(PathError {
                     path: prefix.clone(),
@@ -191,7 +217,8 @@ fn check_array_completeness(directives: &[Directive]) -> ValidationResult {
                         index_seen: *right,
                         index_missing: left + 1,
                     },
-                })?;
+                })
+                .context(ValidationSnafu { directive: *i })?;
             }
         }
     }
@@ -0,0 +1,211 @@
+use crate::directive::decode_string;
+use crate::node::Members;
+use crate::node::Node;
+use crate::output::Style;
+use crate::output::members;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Writes a tree as a YAML document in block style.
+///
+/// Strings are always double-quoted, and numbers are written as in JSON, except for floats with
+/// an exponent, see [`write_number`].
+/// Keys are written without quotes unless they could be mistaken for anything but a string.
+/// Of the style, only the key order is taken into account.
+pub fn to_yaml(node: &Node, style: &Style) -> String {
+    let mut out = String::new();
+    match node {
+        Node::Object(object) if !object.is_empty() => {
+            write_mapping(&mut out, object, style, 0, false)
+        }
+        Node::Array(array) if !array.is_empty() => write_sequence(&mut out, array, style, 0, false),
+        _ => {
+            write_scalar(&mut out, node);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Writes the members of a mapping on lines of their own, except the first member if it is to
+/// continue the current line.
+fn write_mapping(out: &mut String, object: &Members, style: &Style, depth: usize, inline: bool) {
+    for (i, (key, member)) in members(object, style).enumerate() {
+        if i > 0 || !inline {
+            write_indent(out, depth);
+        }
+        write_key(out, key);
+        out.push(':');
+        match member {
+            Node::Object(object) if !object.is_empty() => {
+                out.push('\n');
+                write_mapping(out, object, style, depth + 1, false);
+            }
+            Node::Array(array) if !array.is_empty() => {
+                out.push('\n');
+                write_sequence(out, array, style, depth + 1, false);
+            }
+            _ => {
+                out.push(' ');
+                write_scalar(out, member);
+                out.push('\n');
+            }
+        }
+    }
+}
+
+/// Writes the elements of a sequence on lines of their own, except the first element if it is
+/// to continue the current line.
+fn write_sequence(
+    out: &mut String,
+    array: &BTreeMap<u32, Node>,
+    style: &Style,
+    depth: usize,
+    inline: bool,
+) {
+    for (i, element) in array.values().enumerate() {
+        if i > 0 || !inline {
+            write_indent(out, depth);
+        }
+        out.push_str("- ");
+        match element {
+            Node::Object(object) if !object.is_empty() => {
+                write_mapping(out, object, style, depth + 1, true)
+            }
+            Node::Array(array) if !array.is_empty() => {
+                write_sequence(out, array, style, depth + 1, true)
+            }
+            _ => {
+                write_scalar(out, element);
+                out.push('\n');
+            }
+        }
+    }
+}
+
+fn write_indent(out: &mut String, depth: usize) {
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+/// Writes a leaf, i.e. a JSON scalar or an empty object or array.
+fn write_scalar(out: &mut String, node: &Node) {
+    match node {
+        Node::Value(value) if value.starts_with('"') => write_string(out, value),
+        Node::Value(value) if value.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => {
+            write_number(out, value)
+        }
+        Node::Value(value) => out.push_str(value),
+        Node::Array(_) => out.push_str("[]"),
+        Node::Object(_) => out.push_str("{}"),
+    }
+}
+
+/// Writes a JSON number so that it is read back as a number by YAML 1.1 parsers as well.
+///
+/// YAML 1.1 floats need a dot in the mantissa and a sign in the exponent, so e.g. `1e5` is
+/// written as `1.0e+5`, which YAML 1.2 parsers read as the same number.
+fn write_number(out: &mut String, value: &str) {
+    let Some(e) = value.find(['e', 'E']) else {
+        out.push_str(value);
+        return;
+    };
+    let (mantissa, exponent) = (&value[..e], &value[e + 1..]);
+    out.push_str(mantissa);
+    if !mantissa.contains('.') {
+        out.push_str(".0");
+    }
+    out.push_str(&value[e..e + 1]);
+    if !exponent.starts_with(['+', '-']) {
+        out.push('+');
+    }
+    out.push_str(exponent);
+}
+
+fn write_key(out: &mut String, key: &str) {
+    let is_plain = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !matches!(
+            key.to_ascii_lowercase().as_str(),
+            "y" | "n" | "yes" | "no" | "on" | "off" | "true" | "false" | "null"
+        );
+    if is_plain {
+        out.push_str(key);
+    } else {
+        write_string(out, &format!(r#""{}""#, key));
+    }
+}
+
+/// Rewrites a JSON string as a YAML double-quoted scalar.
+///
+/// YAML escapes differ from JSON escapes, e.g. YAML has no surrogate pairs, so the string is
+/// unescaped and escaped again, leaving only non-printable characters escaped.
+fn write_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in decode_string(&value[1..value.len() - 1]).chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' '..='~'
+            | '\u{a0}'..='\u{d7ff}'
+            | '\u{e000}'..='\u{fefe}'
+            | '\u{ff00}'..='\u{fffd}' => out.push(c),
+            '\u{10000}'.. => out.push(c),
+            _ => write!(out, "\\u{:04x}", c as u32).expect("writing to a string"),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(document: &str) -> String {
+        to_yaml(&document.parse().unwrap(), &Style::default())
+    }
+
+    #[test]
+    fn write_block_style() {
+        assert_eq!(
+            yaml(r#"{"b":[1.50,{"x":true,"w":[]},[null,["z"]]],"a":{"c":{"d":-1e5},"e":{}}}"#),
+            concat!(
+                "a:\n",
+                "  c:\n",
+                "    d: -1.0e+5\n",
+                "  e: {}\n",
+                "b:\n",
+                "  - 1.50\n",
+                "  - w: []\n",
+                "    x: true\n",
+                "  - - null\n",
+                "    - - \"z\"\n",
+            )
+        );
+        assert_eq!(yaml("42"), "42\n");
+        assert_eq!(
+            yaml("[1E5,2.5e-3,0.5E+1,-0]"),
+            "- 1.0E+5\n- 2.5e-3\n- 0.5E+1\n- -0\n"
+        );
+        assert_eq!(yaml("[]"), "[]\n");
+    }
+
+    #[test]
+    fn quote_strings_and_ambiguous_keys() {
+        assert_eq!(
+            yaml(r#"{"no":"yes","1":"\u00e9\ud83d\ude00\/\t\u007f","a b":"\"","_k-1":"\\"}"#),
+            concat!(
+                "\"1\": \"é😀/\\t\\u007f\"\n",
+                "_k-1: \"\\\\\"\n",
+                "\"a b\": \"\\\"\"\n",
+                "\"no\": \"yes\"\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,558 @@
+#[path = "../src/testing.rs"]
+mod testing;
+
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+use testing::TempDir;
+
+struct Output {
+    code: i32,
+    stdout: Vec<u8>,
+    stderr: String,
+}
+
+impl Output {
+    fn stdout(&self) -> &str {
+        std::str::from_utf8(&self.stdout).expect("stdout should be UTF-8")
+    }
+}
+
+fn run(program: &str, args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(program)
+        .args(args)
+        .env_remove("NO_COLOR")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run binary");
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(stdin)
+        .expect("failed to write stdin");
+    let output = child.wait_with_output().expect("failed to wait for binary");
+    Output {
+        code: output.status.code().expect("binary should exit normally"),
+        stdout: output.stdout,
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+fn mkjson(args: &[&str], stdin: &[u8]) -> Output {
+    run(env!("CARGO_BIN_EXE_mkjson"), args, stdin)
+}
+
+fn mkjsonrpc(args: &[&str]) -> Output {
+    run(env!("CARGO_BIN_EXE_mkjsonrpc"), args, b"")
+}
+
+/// Lists the names of the entries of a temporary directory, sorted.
+fn entries(dir: &TempDir) -> Vec<String> {
+    let mut entries: Vec<_> = std::fs::read_dir(&dir.0)
+        .expect("failed to read temporary directory")
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    entries
+}
+
+#[test]
+fn edit_files_in_place() {
+    let dir = TempDir::new("in-place");
+    let file = dir.file(
+        "config.json",
+        "{\n    \"b\": 1,\n    \"a\": {\"x\": true}\n}\n",
+    );
+
+    let output = mkjson(&["--in-place", &file, "!b", "a.y:2", "c=z"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout(), "");
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "{\n    \"a\": {\n        \"x\": true,\n        \"y\": 2\n    },\n    \"c\": \"z\"\n}\n"
+    );
+    assert_eq!(entries(&dir), ["config.json"]);
+
+    let output = mkjson(&["--in-place", &file, "--normalize"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "{\"a\":{\"x\":true,\"y\":2},\"c\":\"z\"}\n"
+    );
+
+    let output = mkjson(&["--in-place", &file, "!."], b"");
+    assert_eq!(output.code, 2);
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "{\"a\":{\"x\":true,\"y\":2},\"c\":\"z\"}\n"
+    );
+    assert_eq!(entries(&dir), ["config.json"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn edit_symbolic_links_in_place() {
+    let dir = TempDir::new("in-place-link");
+    let file = dir.file("config.json", "{\"a\":1}\n");
+    let link = dir.0.join("link.json");
+    std::os::unix::fs::symlink("config.json", &link).unwrap();
+
+    let output = mkjson(&["--in-place", &link.to_string_lossy(), "b:2"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "{\"a\":1,\"b\":2}\n"
+    );
+    assert_eq!(entries(&dir), ["config.json", "link.json"]);
+}
+
+#[test]
+fn explode_documents() {
+    let output = mkjson(&["--explode"], br#"{"b":"x y", "a":[1],"c":{}}"#);
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout(), "'b=x y' \\\na.0:1 \\\n'c:{}'\n");
+
+    let output = mkjson(&["--explode"], b"[1,");
+    assert_eq!(output.code, 2);
+    assert_eq!(output.stdout(), "");
+    assert_eq!(
+        output.stderr,
+        "input error: base document \"-\": position 1: invalid json value\n"
+    );
+}
+
+#[test]
+fn compare_documents() {
+    let dir = TempDir::new("diff");
+    let old = dir.file("old.json", r#"{"a":1,"b":{}}"#);
+    let new = dir.file("new.json", r#"{"a":2,"b":{"c":[]}}"#);
+    let output = mkjson(&["diff", &old, &new], b"");
+    assert_eq!(output.code, 1, "{}", output.stderr);
+    assert_eq!(output.stdout(), "~ a: 1 -> 2\n+ b.c: []\n");
+
+    let output = mkjson(&["diff", "--directives", &old, &new], b"");
+    assert_eq!(output.code, 1, "{}", output.stderr);
+    assert_eq!(output.stdout(), "a:2 \\\n'b.c:[]'\n");
+
+    let output = mkjson(&["diff", &old, &old], b"");
+    assert_eq!((output.code, output.stdout()), (0, ""));
+
+    let missing = dir.0.join("missing.json");
+    let output = mkjson(&["diff", &old, &missing.to_string_lossy()], b"");
+    assert_eq!(output.code, 2);
+    assert!(
+        output.stderr.starts_with("input error: base document "),
+        "{}",
+        output.stderr
+    );
+}
+
+#[test]
+fn write_pretty_output() {
+    let output = mkjson(&["--pretty", "a.b:1", "c:[1,{}]"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\n  \"a\": {\n    \"b\": 1\n  },\n  \"c\": [\n    1,\n    {}\n  ]\n}\n"
+    );
+
+    let output = mkjson(&["--pretty", "--indent", "tab", "a.b:1"], b"");
+    assert_eq!(output.stdout(), "{\n\t\"a\": {\n\t\t\"b\": 1\n\t}\n}\n");
+
+    let output = mkjson(&["--indent", "2", "a:1"], b"");
+    assert_eq!(output.code, 2);
+    assert_eq!(output.stdout(), "");
+}
+
+#[test]
+fn write_colored_output() {
+    let output = mkjson(&["--color", "always", "a=b", "c:[1,null]"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\x1b[34;1m\"a\"\x1b[0m:\x1b[32m\"b\"\x1b[0m,\x1b[34;1m\"c\"\x1b[0m:\
+         [\x1b[36m1\x1b[0m,\x1b[33mnull\x1b[0m]}\n"
+    );
+
+    for when in ["auto", "never"] {
+        let output = mkjson(&["--color", when, "a=b"], b"");
+        assert_eq!(output.stdout(), "{\"a\":\"b\"}\n", "{}", when);
+    }
+}
+
+#[test]
+fn mkjsonrpc_writes_requests() {
+    let output = mkjsonrpc(&["-m", "sum", "-i", "1", "b:2", "a:1"]);
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\"id\":1,\"jsonrpc\":\"2.0\",\"method\":\"sum\",\"params\":{\"a\":1,\"b\":2}}\n"
+    );
+
+    let output = mkjsonrpc(&["--preserve-order", "-m", "sum", "-i", "1", "b:2", "a:1"]);
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\"jsonrpc\":\"2.0\",\"method\":\"sum\",\"params\":{\"b\":2,\"a\":1},\"id\":1}\n"
+    );
+
+    let output = mkjsonrpc(&["-m", "ping"]);
+    assert_eq!(
+        output.stdout(),
+        "{\"jsonrpc\":\"2.0\",\"method\":\"ping\"}\n"
+    );
+}
+
+#[test]
+fn preserve_key_order() {
+    let output = mkjson(
+        &["--preserve-order", "b.y:1", "b.x:2", r#"a:{"z":1,"c":2}"#],
+        b"",
+    );
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\"b\":{\"y\":1,\"x\":2},\"a\":{\"z\":1,\"c\":2}}\n"
+    );
+
+    let output = mkjson(
+        &["--preserve-order", "--base", "-", "m=1"],
+        br#"{"z":1,"a":2}"#,
+    );
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout(), "{\"z\":1,\"a\":2,\"m\":\"1\"}\n");
+}
+
+#[test]
+fn write_canonical_json() {
+    let output = mkjson(&["--canonical", "b:1.50e1", "a:0.1"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout(), "{\"a\":0.1,\"b\":15}\n");
+
+    let output = mkjson(&["--canonical", "a:0.30000000000000001"], b"");
+    assert_eq!(output.code, 2);
+    assert_eq!(
+        output.stderr,
+        "input error: path a: number 0.30000000000000001 differs from the shortest form of the \
+         nearest double\n"
+    );
+}
+
+#[test]
+fn escape_non_ascii_characters() {
+    let output = mkjson(&["--ascii", "a=é😀", "\"ü\":1"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\"a\":\"\\u00e9\\ud83d\\ude00\",\"\\u00fc\":1}\n"
+    );
+
+    let output = mkjson(&["--ascii", "--pretty", "a=é"], b"");
+    assert_eq!(output.stdout(), "{\n  \"a\": \"\\u00e9\"\n}\n");
+
+    let output = mkjson(&["--ascii", "--canonical", "a=é"], b"");
+    assert_eq!(output.code, 2);
+    assert_eq!(output.stdout(), "");
+}
+
+#[test]
+fn escape_html_characters() {
+    let output = mkjson(&["--html-safe", "a=<b>&amp;</b>", "b='x'"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\"a\":\"\\u003cb\\u003e\\u0026amp;\\u003c/b\\u003e\",\"b\":\"'x'\"}\n"
+    );
+
+    let output = mkjson(&["--html-safe", "a=x\u{2028}y\u{2029}"], b"");
+    assert_eq!(output.stdout(), "{\"a\":\"x\\u2028y\\u2029\"}\n");
+
+    let output = mkjson(&["--ascii", "--html-safe", "a=<é>"], b"");
+    assert_eq!(output.stdout(), "{\"a\":\"\\u003c\\u00e9\\u003e\"}\n");
+}
+
+#[test]
+fn write_yaml() {
+    let output = mkjson(
+        &[
+            "--output-format",
+            "yaml",
+            "a:1e5",
+            "b=x",
+            r#"c:[1,{"d":null}]"#,
+        ],
+        b"",
+    );
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "a: 1.0e+5\nb: \"x\"\nc:\n  - 1\n  - d: null\n"
+    );
+}
+
+#[test]
+fn write_toml() {
+    let output = mkjson(
+        &["--output-format", "toml", "a:[1,\"x\"]", "b.c=y", "d:1.5"],
+        b"",
+    );
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "a = [1, \"x\"]\nd = 1.5\n\n[b]\nc = \"y\"\n"
+    );
+
+    for (directive, message) in [
+        ("a:null", "input error: path a: TOML has no null\n"),
+        (
+            "a:1e-400",
+            "input error: path a: number 1e-400 is out of range for TOML\n",
+        ),
+    ] {
+        let output = mkjson(&["--output-format", "toml", directive], b"");
+        assert_eq!(output.code, 2);
+        assert_eq!(output.stdout(), "");
+        assert_eq!(output.stderr, message);
+    }
+}
+
+#[test]
+fn write_binary_formats() {
+    let output = mkjson(&["--output-format", "cbor", "a:1"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout, [0xa1, 0x61, 0x61, 0x01]);
+
+    let output = mkjson(
+        &[
+            "--output-format",
+            "cbor",
+            "--deterministic",
+            "bb:1",
+            "a:2.5",
+        ],
+        b"",
+    );
+    assert_eq!(
+        output.stdout,
+        [0xa2, 0x61, 0x61, 0xf9, 0x41, 0x00, 0x62, 0x62, 0x62, 0x01]
+    );
+
+    let output = mkjson(&["--output-format", "msgpack", "a:[true,\"x\"]"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout, [0x81, 0xa1, 0x61, 0x92, 0xc3, 0xa1, 0x78]);
+
+    let output = mkjson(&["--deterministic", "a:1"], b"");
+    assert_eq!(output.code, 2);
+    assert_eq!(output.stdout, b"");
+}
+
+#[test]
+fn compose_documents_from_records() {
+    let output = mkjson(
+        &["--records", "csv", "name=$1", "age#=$2"],
+        b"Alice,30\n\"Smith, Bob\",41\n",
+    );
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(
+        output.stdout(),
+        "{\"age\":30,\"name\":\"Alice\"}\n{\"age\":41,\"name\":\"Smith, Bob\"}\n"
+    );
+
+    let output = mkjson(
+        &["--records", "csv", "--color", "always", "--ascii", "a=$1"],
+        "é\n".as_bytes(),
+    );
+    assert_eq!(
+        output.stdout(),
+        "{\x1b[34;1m\"a\"\x1b[0m:\x1b[32m\"\\u00e9\"\x1b[0m}\n"
+    );
+
+    let output = mkjson(&["--records", "csv", "a=$2"], b"x,y\nz\n");
+    assert_eq!(output.code, 2);
+    assert_eq!(output.stdout(), "{\"a\":\"y\"}\n");
+    assert_eq!(
+        output.stderr,
+        "input error: record 2: field $2 is missing, the record has only 1\n"
+    );
+}
+
+#[test]
+fn skip_bad_records() {
+    let output = mkjson(
+        &[
+            "--records",
+            "csv",
+            "--skip-bad-records",
+            "name=$1",
+            "age#=$2",
+        ],
+        b"Alice,30\nBob,forty\nCarol,5\n",
+    );
+    assert_eq!(output.code, 2);
+    assert_eq!(
+        output.stdout(),
+        "{\"age\":30,\"name\":\"Alice\"}\n{\"age\":5,\"name\":\"Carol\"}\n"
+    );
+    assert!(
+        output.stderr.starts_with("input error: record 2: "),
+        "{}",
+        output.stderr
+    );
+
+    let output = mkjson(
+        &["--records", "csv", "--skip-bad-records", "name=$1"],
+        b"Alice\n",
+    );
+    assert_eq!(output.code, 0, "{}", output.stderr);
+}
+
+#[test]
+fn reject_stdin_sources_with_records() {
+    let output = mkjson(&["--records", "csv", "a=$1", "b@=-"], b"x,y\n");
+    assert_eq!(output.code, 2);
+    assert_eq!(output.stdout(), "");
+    assert_eq!(
+        output.stderr,
+        "input error: directive \"b@=-\": stdin holds the records, so no value can be read \
+         from it\n"
+    );
+
+    let output = mkjson(&["--records", "csv", "a@:$1"], b"-\n");
+    assert_eq!(output.code, 2);
+    assert!(
+        output.stderr.starts_with("input error: record 1: "),
+        "{}",
+        output.stderr
+    );
+}
+
+#[test]
+fn reject_conflicting_record_flags() {
+    for args in [
+        &["--records", "csv", "--stdin", "a=$1"][..],
+        &["--records", "csv", "--pretty", "a=$1"],
+        &["--records", "csv", "--output-format", "yaml", "a=$1"],
+        &["--skip-bad-records", "a=x"],
+    ] {
+        let output = mkjson(args, b"");
+        assert_eq!(output.code, 2, "{:?}", args);
+        assert_eq!(output.stdout(), "", "{:?}", args);
+        assert!(
+            output.stderr.starts_with("error: "),
+            "{:?}: {}",
+            args,
+            output.stderr
+        );
+    }
+}
+
+#[test]
+fn write_several_documents() {
+    let output = mkjson(&["a:1", ",,", "b:2"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout(), "{\"a\":1}\n{\"b\":2}\n");
+
+    let output = mkjson(&["--documents", "array", "a:1", ",,", "b:2"], b"");
+    assert_eq!(output.stdout(), "[{\"a\":1},{\"b\":2}]\n");
+
+    let output = mkjson(&["--documents", "json-seq", "a:1"], b"");
+    assert_eq!(output.stdout(), "\x1e{\"a\":1}\n");
+
+    let output = mkjson(&["a:1", ",,", "b:x"], b"");
+    assert_eq!(output.code, 2);
+    assert_eq!(output.stdout(), "");
+    assert!(
+        output.stderr.starts_with("input error: document 2: "),
+        "{}",
+        output.stderr
+    );
+}
+
+#[test]
+fn separate_documents_in_directive_files() {
+    let dir = TempDir::new("documents");
+    let file = dir.file("documents.args", "a:1\n,,\nb:2\n");
+    let output = mkjson(&[&format!("@{file}"), "c:3"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout(), "{\"a\":1}\n{\"b\":2,\"c\":3}\n");
+
+    let outer = dir.file("outer.args", "@documents.args\n,,\nd:4\n");
+    let output = mkjson(&[&format!("@{outer}")], b"");
+    assert_eq!(output.stdout(), "{\"a\":1}\n{\"b\":2}\n{\"d\":4}\n");
+
+    let continued = dir.file("continued.args", "a=x\\\n,,\nb:2\n");
+    let output = mkjson(&[&format!("@{continued}")], b"");
+    assert_eq!(output.stdout(), "{\"a\":\"x\\n,,\",\"b\":2}\n");
+}
+
+#[test]
+fn reject_conflicting_document_flags() {
+    for args in [
+        &["--documents", "array", "--canonical", "a:1"][..],
+        &["--records", "csv", "--documents", "array", "a=$1"],
+        &["--pretty", "a:1", ",,", "b:1"],
+        &["--canonical", "a:1", ",,", "b:1"],
+    ] {
+        let output = mkjson(args, b"");
+        assert_eq!(output.code, 2, "{:?}", args);
+        assert_eq!(output.stdout(), "", "{:?}", args);
+        assert!(
+            output.stderr.starts_with("error: "),
+            "{:?}: {}",
+            args,
+            output.stderr
+        );
+    }
+}
+
+#[test]
+fn frame_documents() {
+    let output = mkjson(&["--frame", "netstring", "a:1", ",,", "b:22"], b"");
+    assert_eq!(output.stdout(), "7:{\"a\":1},8:{\"b\":22},");
+
+    let output = mkjson(&["--frame", "u32be", "a:1"], b"");
+    assert_eq!(output.stdout, b"\0\0\0\x07{\"a\":1}");
+
+    let output = mkjson(&["--output-format", "yaml", "a:1"], b"");
+    assert_eq!(output.stdout(), "a: 1\n");
+
+    let output = mkjson(
+        &["--records", "tsv", "--frame", "json-seq", "a=$1"],
+        b"x\ny\n",
+    );
+    assert_eq!(output.stdout(), "\x1e{\"a\":\"x\"}\n\x1e{\"a\":\"y\"}\n");
+}
+
+#[test]
+fn write_documents_without_frame() {
+    let output = mkjson(&["--canonical", "--frame", "none", "a:1"], b"");
+    assert_eq!(output.code, 0, "{}", output.stderr);
+    assert_eq!(output.stdout(), "{\"a\":1}");
+
+    let output = mkjson(&["--frame", "none", "a:1", ",,", "b:2"], b"");
+    assert_eq!(output.stdout(), "{\"a\":1}{\"b\":2}");
+}
+
+#[test]
+fn reject_conflicting_frame_flags() {
+    for args in [
+        &["--documents", "json-seq", "--frame", "netstring", "a:1"][..],
+        &["--frame", "none", "--explode"],
+        &["--frame", "none", "--in-place", "file.json", "a:1"],
+        &["--frame", "u64be", "a:1"],
+    ] {
+        let output = mkjson(args, b"");
+        assert_eq!(output.code, 2, "{:?}", args);
+        assert_eq!(output.stdout(), "", "{:?}", args);
+        assert!(
+            output.stderr.starts_with("error: "),
+            "{:?}: {}",
+            args,
+            output.stderr
+        );
+    }
+}